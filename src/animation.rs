@@ -1,4 +1,9 @@
 use crate::math::{f32x4, Transform, Vec2, Vec3, Wec2, Wec3};
+use crate::spectrum::{Srgb, WSrgb};
+
+use std::collections::hash_map::HashMap;
+use std::ops::{Add, Mul, Sub};
+use std::path::Path;
 
 /// A generic object which contains a property of type T which is sequenced over time.
 pub trait Sequenced<T>: Send + Sync {
@@ -9,6 +14,69 @@ pub trait WSequenced<T>: Send + Sync {
     fn sample_at(&self, t: f32x4) -> T;
 }
 
+/// A wide type which can be assembled from one narrow value per lane.
+pub trait FromLanes<T> {
+    fn from_lanes(lanes: [T; 4]) -> Self;
+}
+
+impl FromLanes<f32> for f32x4 {
+    #[inline]
+    fn from_lanes(lanes: [f32; 4]) -> Self {
+        f32x4::from(lanes)
+    }
+}
+
+impl FromLanes<Vec2> for Wec2 {
+    #[inline]
+    fn from_lanes(lanes: [Vec2; 4]) -> Self {
+        Wec2::from(lanes)
+    }
+}
+
+impl FromLanes<Vec3> for Wec3 {
+    #[inline]
+    fn from_lanes(lanes: [Vec3; 4]) -> Self {
+        Wec3::from(lanes)
+    }
+}
+
+impl FromLanes<Srgb> for WSrgb {
+    #[inline]
+    fn from_lanes(lanes: [Srgb; 4]) -> Self {
+        WSrgb::from(lanes)
+    }
+}
+
+impl FromLanes<Transform> for Transform {
+    /// `Transform` is already wide, so lane `i` is taken from lane `i` of the `i`th transform.
+    #[inline]
+    fn from_lanes(lanes: [Transform; 4]) -> Self {
+        let p0: [Vec3; 4] = lanes[0].position.into();
+        let p1: [Vec3; 4] = lanes[1].position.into();
+        let p2: [Vec3; 4] = lanes[2].position.into();
+        let p3: [Vec3; 4] = lanes[3].position.into();
+        Transform {
+            position: Wec3::from([p0[0], p1[1], p2[2], p3[3]]),
+        }
+    }
+}
+
+/// Sample `seq` at the time of each lane of `t`, assembling the results into a wide value.
+#[inline]
+pub fn sample_lanes<T, W, S>(seq: &S, t: f32x4) -> W
+where
+    W: FromLanes<T>,
+    S: Sequenced<T> + ?Sized,
+{
+    let ts = t.as_ref();
+    W::from_lanes([
+        Sequenced::sample_at(seq, ts[0]),
+        Sequenced::sample_at(seq, ts[1]),
+        Sequenced::sample_at(seq, ts[2]),
+        Sequenced::sample_at(seq, ts[3]),
+    ])
+}
+
 macro_rules! impl_inherent_sequenced {
     ($($type:ty,)*) => {
         $(impl Sequenced<$type> for $type {
@@ -36,21 +104,15 @@ macro_rules! impl_wsequenced_for_sequenced {
         $(impl WSequenced<$wtype> for $type {
             #[inline]
             fn sample_at(&self, t: f32x4) -> $wtype {
-                let ts = t.as_ref();
-                <$wtype>::from([
-                    Sequenced::sample_at(self, ts[0]),
-                    Sequenced::sample_at(self, ts[1]),
-                    Sequenced::sample_at(self, ts[2]),
-                    Sequenced::sample_at(self, ts[3]),
-                ])
+                sample_lanes(self, t)
             }
         })*
     }
 }
 
-impl_inherent_sequenced!(f32, usize, u32, i32, isize, Vec2, Vec3, Transform,);
-impl_inherent_wsequenced!(f32x4, Wec3, Wec2,);
-impl_wsequenced_for_sequenced!(f32 => f32x4, Vec2 => Wec2, Vec3 => Wec3);
+impl_inherent_sequenced!(f32, usize, u32, i32, isize, Vec2, Vec3, Srgb, Transform,);
+impl_inherent_wsequenced!(f32x4, Wec3, Wec2, WSrgb,);
+impl_wsequenced_for_sequenced!(f32 => f32x4, Vec2 => Wec2, Vec3 => Wec3, Srgb => WSrgb);
 
 impl<T, F: Fn(f32) -> T + Send + Sync> Sequenced<T> for F {
     #[inline]
@@ -59,11 +121,359 @@ impl<T, F: Fn(f32) -> T + Send + Sync> Sequenced<T> for F {
     }
 }
 
-impl<F: Fn(f32) -> Vec3 + Send + Sync> WSequenced<Wec3> for F {
+impl<T, W, F> WSequenced<W> for F
+where
+    W: FromLanes<T>,
+    F: Fn(f32) -> T + Send + Sync,
+{
     #[inline]
-    fn sample_at(&self, t: f32x4) -> Wec3 {
+    fn sample_at(&self, t: f32x4) -> W {
         let ts = t.as_ref();
-        [self(ts[0]), self(ts[0]), self(ts[0]), self(ts[0])].into()
+        W::from_lanes([self(ts[0]), self(ts[1]), self(ts[2]), self(ts[3])])
+    }
+}
+
+/// How a `Track` interpolates between its keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    CatmullRom,
+}
+
+impl Interpolation {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "step" => Some(Interpolation::Step),
+            "linear" => Some(Interpolation::Linear),
+            "catmull_rom" => Some(Interpolation::CatmullRom),
+            _ => None,
+        }
+    }
+}
+
+/// A value which can be keyframed in a `Track`.
+pub trait Keyframe:
+    Copy + Send + Sync + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self>
+{
+    /// The number of scalar components a key of this type is made of in an animation file.
+    const COMPONENTS: usize;
+
+    fn from_components(components: &[f32]) -> Self;
+}
+
+impl Keyframe for f32 {
+    const COMPONENTS: usize = 1;
+
+    fn from_components(components: &[f32]) -> Self {
+        components[0]
+    }
+}
+
+impl Keyframe for Vec2 {
+    const COMPONENTS: usize = 2;
+
+    fn from_components(components: &[f32]) -> Self {
+        Vec2::new(components[0], components[1])
+    }
+}
+
+impl Keyframe for Vec3 {
+    const COMPONENTS: usize = 3;
+
+    fn from_components(components: &[f32]) -> Self {
+        Vec3::new(components[0], components[1], components[2])
+    }
+}
+
+impl Keyframe for Srgb {
+    const COMPONENTS: usize = 3;
+
+    fn from_components(components: &[f32]) -> Self {
+        Srgb::new(components[0], components[1], components[2])
+    }
+}
+
+/// A keyframed animation curve of a single value. Sampling before the first key or after
+/// the last key holds the value of that key.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    interpolation: Interpolation,
+    times: Vec<f32>,
+    values: Vec<T>,
+}
+
+impl<T: Keyframe> Track<T> {
+    /// Create a track from a list of `(time, value)` keys, which must be non-empty, have
+    /// finite times and be sorted by time.
+    pub fn new(interpolation: Interpolation, keys: Vec<(f32, T)>) -> Result<Self, String> {
+        if keys.is_empty() {
+            return Err(String::from("Attempted to create a track with no keys"));
+        }
+        if keys.iter().any(|(time, _)| !time.is_finite()) {
+            return Err(String::from("Track key times must be finite"));
+        }
+        if keys.windows(2).any(|pair| pair[1].0 < pair[0].0) {
+            return Err(String::from("Track keys must be sorted by time"));
+        }
+        let (times, values) = keys.into_iter().unzip();
+        Ok(Track {
+            interpolation,
+            times,
+            values,
+        })
+    }
+
+    /// A track which holds the same value for all time.
+    pub fn constant(value: T) -> Self {
+        Track {
+            interpolation: Interpolation::Step,
+            times: vec![0.0],
+            values: vec![value],
+        }
+    }
+
+    pub fn sample(&self, t: f32) -> T {
+        let last = self.times.len() - 1;
+        // invalid rays carry a NaN time, so make sure those don't reach the search below
+        if t.is_nan() || t <= self.times[0] {
+            return self.values[0];
+        }
+        if t >= self.times[last] {
+            return self.values[last];
+        }
+
+        // index of the key which starts the segment containing t
+        let i = match self
+            .times
+            .binary_search_by(|time| time.partial_cmp(&t).unwrap())
+        {
+            Ok(i) => return self.values[i],
+            Err(i) => i - 1,
+        };
+
+        let (t1, t2) = (self.times[i], self.times[i + 1]);
+        let (p1, p2) = (self.values[i], self.values[i + 1]);
+        let s = (t - t1) / (t2 - t1);
+
+        match self.interpolation {
+            Interpolation::Step => p1,
+            Interpolation::Linear => p1 + (p2 - p1) * s,
+            Interpolation::CatmullRom => {
+                // Non-uniform Catmull-Rom tangents, scaled to the length of this segment.
+                // The end keys are duplicated so the curve comes to rest on them.
+                let (t0, p0) = if i > 0 {
+                    (self.times[i - 1], self.values[i - 1])
+                } else {
+                    (t1, p1)
+                };
+                let (t3, p3) = if i + 2 <= last {
+                    (self.times[i + 2], self.values[i + 2])
+                } else {
+                    (t2, p2)
+                };
+                let m1 = (p2 - p0) * ((t2 - t1) / (t2 - t0));
+                let m2 = (p3 - p1) * ((t2 - t1) / (t3 - t1));
+
+                let s2 = s * s;
+                let s3 = s2 * s;
+                let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+                let h10 = s3 - 2.0 * s2 + s;
+                let h01 = -2.0 * s3 + 3.0 * s2;
+                let h11 = s3 - s2;
+
+                p1 * h00 + m1 * h10 + p2 * h01 + m2 * h11
+            }
+        }
+    }
+}
+
+impl<T: Keyframe> Sequenced<T> for Track<T> {
+    #[inline]
+    fn sample_at(&self, t: f32) -> T {
+        self.sample(t)
+    }
+}
+
+impl<T: Keyframe, W: FromLanes<T>> WSequenced<W> for Track<T> {
+    #[inline]
+    fn sample_at(&self, t: f32x4) -> W {
+        sample_lanes(self, t)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Channel {
+    interpolation: Interpolation,
+    components: usize,
+    keys: Vec<(f32, Vec<f32>)>,
+}
+
+/// A set of named keyframe tracks which can be bound to animatable properties of a scene,
+/// such as `camera.origin` or `mandelbox.scale`.
+///
+/// Animations are loaded from a simple channel file. Each channel starts with a
+/// `channel <name> <step|linear|catmull_rom>` line, followed by one line per key holding the
+/// key's time followed by the components of its value. Blank lines and lines starting with
+/// `#` are ignored.
+///
+/// ```text
+/// # dolly in on the fractal
+/// channel camera.origin catmull_rom
+/// 0.0  3.375 -0.9 4.5
+/// 2.0  2.5   -0.6 3.2
+/// 4.0  1.8   -0.4 2.4
+///
+/// channel mandelbox.scale linear
+/// 0.0  -2.25
+/// 4.0  -2.0
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    channels: HashMap<String, Channel>,
+}
+
+impl Animation {
+    pub fn new() -> Self {
+        Animation {
+            channels: HashMap::new(),
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path.as_ref()).map_err(|e| {
+            format!(
+                "Failed to read animation file {}: {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+        Self::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut animation = Animation::new();
+        let mut current: Option<(String, Channel)> = None;
+
+        for (line_idx, line) in source.lines().enumerate() {
+            let line_num = line_idx + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace().peekable();
+            if words.peek() == Some(&"channel") {
+                words.next();
+                let name = words
+                    .next()
+                    .ok_or_else(|| format!("Line {}: channel is missing a name", line_num))?;
+                let interpolation = words.next().unwrap_or("linear");
+                let interpolation = Interpolation::from_name(interpolation).ok_or_else(|| {
+                    format!(
+                        "Line {}: unknown interpolation '{}'",
+                        line_num, interpolation
+                    )
+                })?;
+
+                if let Some((name, channel)) = current.take() {
+                    animation.insert_channel(name, channel)?;
+                }
+                current = Some((
+                    String::from(name),
+                    Channel {
+                        interpolation,
+                        components: 0,
+                        keys: Vec::new(),
+                    },
+                ));
+            } else {
+                let (name, channel) = current.as_mut().ok_or_else(|| {
+                    format!(
+                        "Line {}: key found before any channel was declared",
+                        line_num
+                    )
+                })?;
+                let numbers = words
+                    .map(|word| word.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("Line {}: {}", line_num, e))?;
+                if numbers.len() < 2 {
+                    return Err(format!(
+                        "Line {}: a key needs a time and at least one value",
+                        line_num
+                    ));
+                }
+
+                let components = numbers.len() - 1;
+                if channel.keys.is_empty() {
+                    channel.components = components;
+                } else if channel.components != components {
+                    return Err(format!(
+                        "Line {}: channel {} has keys with {} and {} components",
+                        line_num, name, channel.components, components
+                    ));
+                }
+                channel.keys.push((numbers[0], numbers[1..].to_vec()));
+            }
+        }
+
+        if let Some((name, channel)) = current.take() {
+            animation.insert_channel(name, channel)?;
+        }
+
+        Ok(animation)
+    }
+
+    fn insert_channel(&mut self, name: String, channel: Channel) -> Result<(), String> {
+        if channel.keys.is_empty() {
+            return Err(format!("Channel {} has no keys", name));
+        }
+        if self.channels.contains_key(&name) {
+            return Err(format!("Channel {} was declared multiple times", name));
+        }
+        self.channels.insert(name, channel);
+        Ok(())
+    }
+
+    /// Names of the channels in the animation, in no particular order.
+    pub fn channel_names(&self) -> impl Iterator<Item = &str> {
+        self.channels.keys().map(|name| name.as_str())
+    }
+
+    /// Get the track bound to `name`, if the animation contains one.
+    pub fn track<T: Keyframe>(&self, name: &str) -> Result<Option<Track<T>>, String> {
+        let channel = match self.channels.get(name) {
+            Some(channel) => channel,
+            None => return Ok(None),
+        };
+
+        if channel.components != T::COMPONENTS {
+            return Err(format!(
+                "Channel {} has {} components but {} were expected",
+                name,
+                channel.components,
+                T::COMPONENTS
+            ));
+        }
+
+        let keys = channel
+            .keys
+            .iter()
+            .map(|(time, components)| (*time, T::from_components(components)))
+            .collect();
+
+        Track::new(channel.interpolation, keys)
+            .map(Some)
+            .map_err(|e| format!("Channel {}: {}", name, e))
+    }
+
+    /// Get the track bound to `name`, or a track holding `default` if the animation does
+    /// not contain one.
+    pub fn track_or<T: Keyframe>(&self, name: &str, default: T) -> Result<Track<T>, String> {
+        Ok(self
+            .track(name)?
+            .unwrap_or_else(|| Track::constant(default)))
     }
 }
 
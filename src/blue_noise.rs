@@ -0,0 +1,163 @@
+//! Tileable blue noise masks, made with Ulichney's void-and-cluster method.
+//!
+//! Using a blue noise mask for each pixel's scramble makes neighboring pixels take
+//! dissimilar samples, so the error left at low sample counts is high frequency noise,
+//! which looks much cleaner than the clumpy white noise of independent scrambles.
+
+use rand::prelude::*;
+
+const SIGMA: f32 = 1.5;
+
+/// The inverse of the golden ratio. Shifting every value of the mask by this each frame
+/// keeps it blue noise in space while giving each pixel a low discrepancy sequence in time.
+const GOLDEN: f32 = 0.618_034;
+
+#[derive(Clone, Debug)]
+pub struct BlueNoiseMask {
+    size: usize,
+    // the rank of each pixel in the void-and-cluster ordering, scaled into [0, 1)
+    values: Vec<f32>,
+}
+
+impl BlueNoiseMask {
+    /// Generate a `size` by `size` mask, which wraps around at its edges so it can be tiled.
+    /// Takes time proportional to `size^4`, so keep it small.
+    pub fn new(size: usize, seed: u64) -> Self {
+        let n = size * size;
+        let mut field = EnergyField::new(size);
+        let mut pattern = vec![false; n];
+        let mut rng = SmallRng::seed_from_u64(seed);
+
+        // start from randomly placed points...
+        let initial = (n / 10).max(1);
+        let mut placed = 0;
+        while placed < initial {
+            let p = rng.gen_range(0, n);
+            if !pattern[p] {
+                pattern[p] = true;
+                field.add(p);
+                placed += 1;
+            }
+        }
+
+        // ...and spread them out by moving the most clustered point into the largest void,
+        // until it would move straight back
+        for _ in 0..n {
+            let cluster = field.tightest_cluster(&pattern);
+            pattern[cluster] = false;
+            field.remove(cluster);
+            let void = field.largest_void(&pattern);
+            pattern[void] = true;
+            field.add(void);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut ranks = vec![0; n];
+
+        // rank the initial points by taking away the most clustered first
+        {
+            let mut pattern = pattern.clone();
+            let mut field = field.clone();
+            for rank in (0..initial).rev() {
+                let cluster = field.tightest_cluster(&pattern);
+                pattern[cluster] = false;
+                field.remove(cluster);
+                ranks[cluster] = rank;
+            }
+        }
+
+        // then rank every other pixel by filling the largest void first
+        for rank in initial..n {
+            let void = field.largest_void(&pattern);
+            pattern[void] = true;
+            field.add(void);
+            ranks[void] = rank;
+        }
+
+        BlueNoiseMask {
+            size,
+            values: ranks
+                .iter()
+                .map(|rank| (*rank as f32 + 0.5) / n as f32)
+                .collect(),
+        }
+    }
+
+    /// The scramble for pixel `(x, y)` in `frame`, tiling the mask across the image.
+    pub fn scramble(&self, x: usize, y: usize, frame: usize) -> f32 {
+        let value = self.values[x % self.size + (y % self.size) * self.size];
+        (value + frame as f32 * GOLDEN).fract()
+    }
+}
+
+/// The sum of a gaussian centered on each point of a pattern, on a grid that wraps around.
+#[derive(Clone)]
+struct EnergyField {
+    size: usize,
+    // the gaussian's value at each offset
+    kernel: Vec<f32>,
+    energy: Vec<f32>,
+}
+
+impl EnergyField {
+    fn new(size: usize) -> Self {
+        let mut kernel = vec![0.0; size * size];
+        for dy in 0..size {
+            for dx in 0..size {
+                let x = dx.min(size - dx) as f32;
+                let y = dy.min(size - dy) as f32;
+                kernel[dx + dy * size] = (-(x * x + y * y) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+        EnergyField {
+            size,
+            kernel,
+            energy: vec![0.0; size * size],
+        }
+    }
+
+    fn splat(&mut self, p: usize, sign: f32) {
+        let (px, py) = (p % self.size, p / self.size);
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let dx = (x + self.size - px) % self.size;
+                let dy = (y + self.size - py) % self.size;
+                self.energy[x + y * self.size] += sign * self.kernel[dx + dy * self.size];
+            }
+        }
+    }
+
+    fn add(&mut self, p: usize) {
+        self.splat(p, 1.0);
+    }
+
+    fn remove(&mut self, p: usize) {
+        self.splat(p, -1.0);
+    }
+
+    /// The point of `pattern` with the most energy.
+    fn tightest_cluster(&self, pattern: &[bool]) -> usize {
+        self.extreme(pattern, true, |a, b| a > b)
+    }
+
+    /// The empty pixel of `pattern` with the least energy.
+    fn largest_void(&self, pattern: &[bool]) -> usize {
+        self.extreme(pattern, false, |a, b| a < b)
+    }
+
+    fn extreme<F: Fn(f32, f32) -> bool>(&self, pattern: &[bool], filled: bool, better: F) -> usize {
+        let mut best = None;
+        for (i, (energy, is_filled)) in self.energy.iter().zip(pattern.iter()).enumerate() {
+            if *is_filled != filled {
+                continue;
+            }
+            match best {
+                Some((_, best_energy)) if !better(*energy, best_energy) => (),
+                _ => best = Some((i, *energy)),
+            }
+        }
+        best.expect("pattern has no pixels to choose from").0
+    }
+}
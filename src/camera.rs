@@ -1,7 +1,11 @@
 use crate::animation::WSequenced;
-use crate::math::{f32x4, RandomSample2d, Vec2, Vec2u, Wec2, Wec3};
+use crate::hitable::HitableStore;
+use crate::math::{f32x4, map_lanes, Distribution2D, RandomSample2d, Vec2, Vec2u, Wec2, Wec3};
 use crate::ray::WRay;
 
+use std::ops::Range;
+use std::path::Path;
+
 pub trait Camera: Send + Sync {
     fn get_rays(
         &self,
@@ -16,26 +20,175 @@ pub trait Camera: Send + Sync {
     /// gets the pixel radius size (half-width) at some t value (distance) from the camera
     /// assumes that the distance is along a ray emitted from the camera.
     fn half_pixel_size_at(&self, t: f32x4) -> f32x4;
+
+    /// The uv coordinates at which `point` is seen at `time`, the inverse of `get_rays`
+    /// through the center of the lens. Lanes where the point can't be seen are NaN, and
+    /// cameras which can't project points return `None`.
+    fn project(&self, _point: Wec3, _time: f32x4) -> Option<Wec2> {
+        None
+    }
+
+    /// Whether `u` wraps around, as longitude does, so that points leaving one side of the
+    /// image reappear on the other.
+    fn wraps_horizontally(&self) -> bool {
+        false
+    }
+
+    /// Distance along each ray before which nothing is visible. Rays returned by `get_rays`
+    /// already start this far from the camera.
+    fn near_clip(&self) -> f32 {
+        0.0
+    }
+
+    /// Distance along each ray from the camera after which nothing is visible.
+    fn far_clip(&self) -> f32 {
+        std::f32::INFINITY
+    }
+
+    /// Called once before rendering a frame whose shutter is open over `time_range`, letting
+    /// the camera update any state that depends on the scene, such as autofocus. Only hits
+    /// within `clip` distance along a ray from the camera should be considered.
+    fn prepare_frame(
+        &mut self,
+        _hitables: &HitableStore,
+        _clip: Range<f32>,
+        _time_range: Range<f32>,
+    ) {
+    }
+}
+
+/// Wraps a camera to clip away everything closer than `near` or further than `far` along
+/// its rays, for example to cut through a wall the camera is inside of.
+pub struct ClippedCamera {
+    camera: Box<dyn Camera>,
+    near: f32,
+    far: f32,
+}
+
+impl ClippedCamera {
+    pub fn new(camera: Box<dyn Camera>, near: f32, far: f32) -> Self {
+        ClippedCamera { camera, near, far }
+    }
+}
+
+impl Camera for ClippedCamera {
+    fn get_rays(
+        &self,
+        scramble: f32,
+        sample_nums: [usize; 4],
+        tile_coord: Vec2u,
+        uv: Wec2,
+        time: f32x4,
+        samples: &[f32x4; 2],
+    ) -> WRay {
+        let mut rays = self
+            .camera
+            .get_rays(scramble, sample_nums, tile_coord, uv, time, samples);
+        rays.origin = rays.point_at(f32x4::from(self.near));
+        rays
+    }
+
+    fn half_pixel_size_at(&self, t: f32x4) -> f32x4 {
+        self.camera.half_pixel_size_at(t + f32x4::from(self.near))
+    }
+
+    fn project(&self, point: Wec3, time: f32x4) -> Option<Wec2> {
+        self.camera.project(point, time)
+    }
+
+    fn wraps_horizontally(&self) -> bool {
+        self.camera.wraps_horizontally()
+    }
+
+    fn near_clip(&self) -> f32 {
+        self.near
+    }
+
+    fn far_clip(&self) -> f32 {
+        self.far
+    }
+
+    fn prepare_frame(&mut self, hitables: &HitableStore, clip: Range<f32>, time_range: Range<f32>) {
+        let clip = clip.start.max(self.near)..clip.end.min(self.far);
+        self.camera.prepare_frame(hitables, clip, time_range);
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 pub struct CameraHandle(usize);
 
-pub struct CameraStore(Vec<Box<dyn Camera>>);
+pub struct CameraStore(Vec<(String, Box<dyn Camera>)>);
 
 impl CameraStore {
     pub fn new() -> Self {
         CameraStore(Vec::new())
     }
 
-    pub fn add_camera(&mut self, material: Box<dyn Camera>) -> CameraHandle {
-        self.0.push(material);
+    pub fn add_camera<S: Into<String>>(
+        &mut self,
+        name: S,
+        camera: Box<dyn Camera>,
+    ) -> CameraHandle {
+        self.0.push((name.into(), camera));
         CameraHandle(self.0.len() - 1)
     }
 
     pub fn get(&self, handle: CameraHandle) -> &dyn Camera {
-        self.0.get(handle.0).map(|b| b.as_ref()).unwrap()
+        self.0.get(handle.0).map(|(_, b)| b.as_ref()).unwrap()
+    }
+
+    pub fn name(&self, handle: CameraHandle) -> &str {
+        self.0.get(handle.0).map(|(name, _)| name.as_str()).unwrap()
+    }
+
+    pub fn find(&self, name: &str) -> Option<CameraHandle> {
+        self.0
+            .iter()
+            .position(|(camera_name, _)| camera_name == name)
+            .map(CameraHandle)
+    }
+
+    pub fn handles(&self) -> impl Iterator<Item = CameraHandle> {
+        (0..self.0.len()).map(CameraHandle)
     }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut dyn Camera> {
+        self.0.iter_mut().map(|(_, b)| b.as_mut())
+    }
+}
+
+/// Project `point` through a perspective camera at `origin` looking toward `at`, whose image
+/// plane one unit in front of it spans `half_size` either side of the center.
+fn project_perspective(origin: Wec3, at: Wec3, up: Wec3, half_size: Wec2, point: Wec3) -> Wec2 {
+    let basis_w = (origin - at).normalized();
+    let basis_u = up.cross(basis_w).normalized();
+    let basis_v = basis_w.cross(basis_u);
+
+    let d = point - origin;
+    let z = -d.dot(basis_w);
+    let half = f32x4::from(0.5);
+    let u = (d.dot(basis_u) / (z * half_size.x) + f32x4::ONE) * half;
+    let v = (d.dot(basis_v) / (z * half_size.y) + f32x4::ONE) * half;
+
+    // points behind the camera aren't visible
+    let in_front = z.cmp_gt(f32x4::ZERO);
+    let nan = f32x4::from(std::f32::NAN);
+    Wec2::new(
+        f32x4::merge(in_front, u, nan),
+        f32x4::merge(in_front, v, nan),
+    )
+}
+
+/// `atan2` of each lane.
+fn atan2_lanes(y: f32x4, x: f32x4) -> f32x4 {
+    let ys = y.as_ref();
+    let xs = x.as_ref();
+    f32x4::from([
+        ys[0].atan2(xs[0]),
+        ys[1].atan2(xs[1]),
+        ys[2].atan2(xs[2]),
+        ys[3].atan2(xs[3]),
+    ])
 }
 
 #[derive(Clone, Copy)]
@@ -116,17 +269,148 @@ where
     fn half_pixel_size_at(&self, t: f32x4) -> f32x4 {
         self.half_pixel_size * t
     }
+
+    fn project(&self, point: Wec3, time: f32x4) -> Option<Wec2> {
+        let origin = self.origin.sample_at(time);
+        let at = self.at.sample_at(time);
+        let up = self.up.sample_at(time);
+        Some(project_perspective(origin, at, up, self.half_size, point))
+    }
 }
-#[derive(Clone, Copy)]
+/// A grayscale image used as the transmission mask of an aperture. It is stretched to
+/// cover the aperture's diameter, and importance sampled so that brighter areas of the mask
+/// are sampled more often.
+#[derive(Clone)]
+pub struct ApertureMask {
+    distribution: Distribution2D,
+}
+
+impl ApertureMask {
+    pub fn new(image: &image::GrayImage) -> Self {
+        let (width, height) = image.dimensions();
+        let func = image
+            .pixels()
+            .map(|pixel| pixel.0[0] as f32 / 255.0)
+            .collect::<Vec<_>>();
+        ApertureMask {
+            distribution: Distribution2D::new(&func, width as usize, height as usize),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let image = image::open(path.as_ref()).map_err(|e| {
+            format!(
+                "Failed to open aperture mask {}: {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+        Ok(Self::new(&image.to_luma()))
+    }
+
+    fn sample(&self, samples: &[f32x4; 2]) -> Wec2 {
+        let us = samples[0].as_ref();
+        let vs = samples[1].as_ref();
+        let mut points = [Vec2::new(0.0, 0.0); 4];
+        for ((point, u), v) in points.iter_mut().zip(us.iter()).zip(vs.iter()) {
+            let p = self.distribution.sample(Vec2::new(*u, *v));
+            // image rows go from top to bottom
+            *point = Vec2::new(p.x * 2.0 - 1.0, 1.0 - p.y * 2.0);
+        }
+        Wec2::from(points)
+    }
+}
+
+/// The shape of a lens aperture, which determines the shape of out of focus highlights.
+#[derive(Clone)]
+pub enum ApertureShape {
+    Circle,
+    /// A regular polygon inscribed in the aperture circle, as formed by aperture blades,
+    /// rotated by `rotation` degrees.
+    Polygon {
+        blades: usize,
+        rotation: f32,
+    },
+    Mask(ApertureMask),
+}
+
+impl ApertureShape {
+    /// Sample a point on the aperture, which spans [-1, 1] on each axis.
+    pub fn sample(&self, samples: &[f32x4; 2]) -> Wec2 {
+        match self {
+            ApertureShape::Circle => Wec2::rand_in_unit_disk(samples),
+            ApertureShape::Polygon { blades, rotation } => {
+                let n = f32x4::from(*blades as f32);
+                let wedge = f32x4::TWO_PI / n;
+
+                // pick a triangle of the polygon, then reuse the remainder of the sample
+                // to pick a point uniformly within it
+                let scaled = samples[0] * n;
+                let side = scaled.floor().min(n - f32x4::ONE);
+                let u = (scaled - side).min(f32x4::ONE);
+
+                let angle = side * wedge + f32x4::from(rotation.to_radians());
+                let (s0, c0) = angle.sin_cos();
+                let (s1, c1) = (angle + wedge).sin_cos();
+
+                let su = u.sqrt();
+                let b0 = su * (f32x4::ONE - samples[1]);
+                let b1 = su * samples[1];
+
+                Wec2::new(c0 * b0 + c1 * b1, s0 * b0 + s1 * b1)
+            }
+            ApertureShape::Mask(mask) => mask.sample(samples),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct ThinLensCamera<A, O, LA, U, F> {
     half_size: Wec2,
     // 2.0 * tan(hfov/2) / resolution.h / 2.0
     half_pixel_size: f32x4,
+    aperture_shape: ApertureShape,
+    // horizontal squeeze of the aperture, making out of focus highlights taller than they are wide
+    anamorphic_squeeze: f32,
     aperture: A,
     origin: O,
     at: LA,
     up: U,
     focus: F,
+    autofocus: Option<Autofocus>,
+}
+
+/// Focus distances found by tracing through `screen_pos` at a few times across the shutter.
+#[derive(Clone, Copy)]
+struct Autofocus {
+    screen_pos: Vec2,
+    times: [f32; 4],
+    focus_dists: [f32; 4],
+}
+
+impl Autofocus {
+    /// Linearly interpolate the traced focus distances at each lane's time.
+    fn focus_dist_at(&self, time: f32x4) -> f32x4 {
+        let times = self.times;
+        let dists = self.focus_dists;
+        map_lanes(time, |t| {
+            if !(t > times[0]) {
+                return dists[0];
+            }
+            for i in 1..4 {
+                if t <= times[i] {
+                    let span = times[i] - times[i - 1];
+                    if span <= 0.0 {
+                        return dists[i];
+                    }
+                    let a = (t - times[i - 1]) / span;
+                    return dists[i - 1] + (dists[i] - dists[i - 1]) * a;
+                }
+            }
+            dists[3]
+        })
+    }
 }
 
 impl<A, O, LA, U, F> ThinLensCamera<A, O, LA, U, F> {
@@ -148,11 +432,55 @@ impl<A, O, LA, U, F> ThinLensCamera<A, O, LA, U, F> {
         ThinLensCamera {
             half_size: Wec2::splat(Vec2::new(half_width, half_height)),
             half_pixel_size,
+            aperture_shape: ApertureShape::Circle,
+            anamorphic_squeeze: 1.0,
             aperture,
             origin,
             at,
             up,
             focus,
+            autofocus: None,
+        }
+    }
+
+    /// Focus on whatever is visible through `screen_pos` (in uv coordinates, so
+    /// `Vec2::new(0.5, 0.5)` is the center of the image) instead of the `focus` point, which
+    /// is only used if nothing is hit.
+    #[allow(dead_code)]
+    pub fn with_autofocus(mut self, screen_pos: Vec2) -> Self {
+        self.autofocus = Some(Autofocus {
+            screen_pos,
+            times: [0.0; 4],
+            focus_dists: [0.0; 4],
+        });
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_aperture_shape(mut self, aperture_shape: ApertureShape) -> Self {
+        self.aperture_shape = aperture_shape;
+        self
+    }
+
+    /// Squeeze the aperture horizontally by `squeeze`, as an anamorphic lens does.
+    #[allow(dead_code)]
+    pub fn with_anamorphic_squeeze(mut self, squeeze: f32) -> Self {
+        self.anamorphic_squeeze = squeeze;
+        self
+    }
+}
+
+impl<A, O, LA, U, F> ThinLensCamera<A, O, LA, U, F>
+where
+    O: WSequenced<Wec3>,
+    LA: WSequenced<Wec3>,
+    U: WSequenced<Wec3>,
+    F: WSequenced<Wec3>,
+{
+    fn focus_dist_at(&self, origin: Wec3, time: f32x4) -> f32x4 {
+        match &self.autofocus {
+            Some(autofocus) => autofocus.focus_dist_at(time),
+            None => (self.focus.sample_at(time) - origin).mag(),
         }
     }
 }
@@ -177,8 +505,7 @@ where
         let origin = self.origin.sample_at(time);
         let at = self.at.sample_at(time);
         let up = self.up.sample_at(time);
-        let focus = self.focus.sample_at(time);
-        let focus_dist = (focus - origin).mag();
+        let focus_dist = self.focus_dist_at(origin, time);
         let aperture = self.aperture.sample_at(time);
 
         let basis_w = (origin - at).normalized();
@@ -192,8 +519,8 @@ where
         let horiz = basis_u * self.half_size.x * focus_dist * f32x4::from(2.0) * uv.x;
         let verti = basis_v * self.half_size.y * focus_dist * f32x4::from(2.0) * uv.y;
 
-        let rd = Wec2::rand_in_unit_disk(samples) * aperture;
-        let offset = basis_u * rd.x + basis_v * rd.y;
+        let rd = self.aperture_shape.sample(samples) * aperture;
+        let offset = basis_u * (rd.x / f32x4::from(self.anamorphic_squeeze)) + basis_v * rd.y;
 
         let origin = origin + offset;
         WRay::new(
@@ -210,6 +537,64 @@ where
     fn half_pixel_size_at(&self, t: f32x4) -> f32x4 {
         self.half_pixel_size * t
     }
+
+    fn project(&self, point: Wec3, time: f32x4) -> Option<Wec2> {
+        let origin = self.origin.sample_at(time);
+        let at = self.at.sample_at(time);
+        let up = self.up.sample_at(time);
+        Some(project_perspective(origin, at, up, self.half_size, point))
+    }
+
+    fn prepare_frame(&mut self, hitables: &HitableStore, clip: Range<f32>, time_range: Range<f32>) {
+        let mut autofocus = match self.autofocus {
+            Some(autofocus) => autofocus,
+            None => return,
+        };
+
+        // one lane at the start, end, and two points within the shutter interval
+        let span = time_range.end - time_range.start;
+        for (i, time) in autofocus.times.iter_mut().enumerate() {
+            *time = time_range.start + span * i as f32 / 3.0;
+        }
+        let time = f32x4::from(autofocus.times);
+
+        let origin = self.origin.sample_at(time);
+        let at = self.at.sample_at(time);
+        let up = self.up.sample_at(time);
+
+        let basis_w = (origin - at).normalized();
+        let basis_u = up.cross(basis_w).normalized();
+        let basis_v = basis_w.cross(basis_u);
+
+        let uv = Wec2::splat(autofocus.screen_pos * 2.0 - Vec2::new(1.0, 1.0));
+        let dir = (basis_u * self.half_size.x * uv.x + basis_v * self.half_size.y * uv.y - basis_w)
+            .normalized();
+
+        let near = f32x4::from(clip.start);
+        let ray = WRay::new(
+            origin + dir * near,
+            dir,
+            time,
+            [Vec2u::zero(); 4],
+            [true; 4],
+            [0.0; 4],
+            [0; 4],
+        );
+
+        let t_max = f32x4::from(clip.end - clip.start);
+        let half_pixel_size = self.half_pixel_size;
+        let t = hitables.closest_hit(&ray, t_max, &|t| half_pixel_size * (t + near));
+        let hit = t.cmp_lt(t_max);
+        let t = t + near;
+
+        // distance to the plane of focus rather than along the ray
+        let focus_dist = t * dir.dot((at - origin).normalized());
+        let fallback = (self.focus.sample_at(time) - origin).mag();
+        let focus_dist = f32x4::merge(hit, focus_dist, fallback);
+
+        autofocus.focus_dists = *focus_dist.as_ref();
+        self.autofocus = Some(autofocus);
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -282,4 +667,414 @@ where
     fn half_pixel_size_at(&self, _t: f32x4) -> f32x4 {
         self.half_pixel_size
     }
+
+    fn project(&self, point: Wec3, time: f32x4) -> Option<Wec2> {
+        let origin = self.origin.sample_at(time);
+        let at = self.at.sample_at(time);
+        let up = self.up.sample_at(time);
+
+        let basis_w = (at - origin).normalized();
+        let basis_u = basis_w.cross(up).normalized();
+        let basis_v = basis_u.cross(basis_w);
+
+        let d = point - origin;
+        Some(Wec2::new(
+            (d.dot(basis_u) + self.half_size.x) / self.full_size.x,
+            (d.dot(basis_v) + self.half_size.y) / self.full_size.y,
+        ))
+    }
+}
+
+/// A 360 degree panoramic camera which maps longitude to the horizontal axis and latitude
+/// to the vertical axis of the image (a lat-long or equirectangular projection). The center
+/// of the image looks toward `at`. Resolution should have a 2:1 aspect ratio.
+#[derive(Clone, Copy)]
+pub struct EquirectangularCamera<O, A, U> {
+    // pi / resolution.h / 2.0
+    half_pixel_size: f32x4,
+    origin: O,
+    at: A,
+    up: U,
+}
+
+impl<O, A, U> EquirectangularCamera<O, A, U> {
+    #[allow(dead_code)]
+    pub fn new(resolution: Vec2, origin: O, at: A, up: U) -> Self {
+        EquirectangularCamera {
+            half_pixel_size: f32x4::from(std::f32::consts::PI / resolution.y / 2.0),
+            origin,
+            at,
+            up,
+        }
+    }
+}
+
+impl<O, A, U> Camera for EquirectangularCamera<O, A, U>
+where
+    O: WSequenced<Wec3>,
+    A: WSequenced<Wec3>,
+    U: WSequenced<Wec3>,
+{
+    fn get_rays(
+        &self,
+        scramble: f32,
+        sample_nums: [usize; 4],
+        tile_coord: Vec2u,
+        uv: Wec2,
+        time: f32x4,
+        _samples: &[f32x4; 2],
+    ) -> WRay {
+        let origin = self.origin.sample_at(time);
+        let at = self.at.sample_at(time);
+        let up = self.up.sample_at(time);
+
+        let basis_w = (origin - at).normalized();
+        let basis_u = up.cross(basis_w).normalized();
+        let basis_v = basis_w.cross(basis_u);
+
+        let half = f32x4::from(0.5);
+        let longitude = (uv.x - half) * f32x4::TWO_PI;
+        let latitude = (uv.y - half) * f32x4::PI;
+        let (sin_long, cos_long) = longitude.sin_cos();
+        let (sin_lat, cos_lat) = latitude.sin_cos();
+
+        let dir =
+            basis_u * (sin_long * cos_lat) + basis_v * sin_lat - basis_w * (cos_long * cos_lat);
+
+        WRay::new(
+            origin,
+            dir.normalized(),
+            time,
+            [tile_coord, tile_coord, tile_coord, tile_coord],
+            [true, true, true, true],
+            [scramble, scramble, scramble, scramble],
+            sample_nums,
+        )
+    }
+
+    fn half_pixel_size_at(&self, t: f32x4) -> f32x4 {
+        self.half_pixel_size * t
+    }
+
+    fn project(&self, point: Wec3, time: f32x4) -> Option<Wec2> {
+        let origin = self.origin.sample_at(time);
+        let at = self.at.sample_at(time);
+        let up = self.up.sample_at(time);
+
+        let basis_w = (origin - at).normalized();
+        let basis_u = up.cross(basis_w).normalized();
+        let basis_v = basis_w.cross(basis_u);
+
+        let dir = (point - origin).normalized();
+        let longitude = atan2_lanes(dir.dot(basis_u), -dir.dot(basis_w));
+        let latitude = map_lanes(dir.dot(basis_v), |y| y.max(-1.0).min(1.0).asin());
+
+        let half = f32x4::from(0.5);
+        Some(Wec2::new(
+            longitude / f32x4::TWO_PI + half,
+            latitude / f32x4::PI + half,
+        ))
+    }
+
+    fn wraps_horizontally(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FisheyeProjection {
+    /// Distance from the center of the image is proportional to the angle from the view
+    /// direction, `r = f * theta`.
+    Equidistant,
+    /// Area in the image is proportional to solid angle, `r = 2f * sin(theta / 2)`.
+    Equisolid,
+}
+
+/// A fisheye camera which projects a field of view of up to 360 degrees onto a circle
+/// inscribed in the image, as used for dome projection. Rays outside the circle are invalid.
+#[derive(Clone, Copy)]
+pub struct FisheyeCamera<O, A, U> {
+    projection: FisheyeProjection,
+    // half of the field of view, in radians
+    half_fov: f32,
+    // scales uv about the image center so that the image circle has a radius of one
+    uv_scale: Wec2,
+    // angle spanned by half a pixel at the center of the image
+    half_pixel_size: f32x4,
+    origin: O,
+    at: A,
+    up: U,
+}
+
+impl<O, A, U> FisheyeCamera<O, A, U> {
+    #[allow(dead_code)]
+    pub fn new(
+        resolution: Vec2,
+        fov: f32,
+        projection: FisheyeProjection,
+        origin: O,
+        at: A,
+        up: U,
+    ) -> Self {
+        let half_fov = fov * std::f32::consts::PI / 180.0 / 2.0;
+        let diameter = resolution.x.min(resolution.y);
+        let half_pixel_size = match projection {
+            FisheyeProjection::Equidistant => half_fov / diameter,
+            FisheyeProjection::Equisolid => 2.0 * (half_fov / 2.0).sin() / diameter,
+        };
+        FisheyeCamera {
+            projection,
+            half_fov,
+            uv_scale: Wec2::splat(Vec2::new(
+                2.0 * resolution.x / diameter,
+                2.0 * resolution.y / diameter,
+            )),
+            half_pixel_size: f32x4::from(half_pixel_size),
+            origin,
+            at,
+            up,
+        }
+    }
+}
+
+impl<O, A, U> Camera for FisheyeCamera<O, A, U>
+where
+    O: WSequenced<Wec3>,
+    A: WSequenced<Wec3>,
+    U: WSequenced<Wec3>,
+{
+    fn get_rays(
+        &self,
+        scramble: f32,
+        sample_nums: [usize; 4],
+        tile_coord: Vec2u,
+        uv: Wec2,
+        time: f32x4,
+        _samples: &[f32x4; 2],
+    ) -> WRay {
+        let origin = self.origin.sample_at(time);
+        let at = self.at.sample_at(time);
+        let up = self.up.sample_at(time);
+
+        let basis_w = (origin - at).normalized();
+        let basis_u = up.cross(basis_w).normalized();
+        let basis_v = basis_w.cross(basis_u);
+
+        let half = f32x4::from(0.5);
+        let x = (uv.x - half) * self.uv_scale.x;
+        let y = (uv.y - half) * self.uv_scale.y;
+        let r = (x * x + y * y).sqrt();
+
+        let theta = match self.projection {
+            FisheyeProjection::Equidistant => r * f32x4::from(self.half_fov),
+            FisheyeProjection::Equisolid => {
+                let k = (self.half_fov / 2.0).sin();
+                map_lanes(r, |r| 2.0 * (r * k).min(1.0).asin())
+            }
+        };
+        let (sin_theta, cos_theta) = theta.sin_cos();
+
+        // direction around the view axis, guarding against the exact center of the image
+        let inv_r = f32x4::merge(r.cmp_gt(f32x4::from(0.000001)), f32x4::ONE / r, f32x4::ZERO);
+        let dir = basis_u * (x * inv_r * sin_theta) + basis_v * (y * inv_r * sin_theta)
+            - basis_w * cos_theta;
+
+        let rs = r.as_ref();
+        let valid = [rs[0] <= 1.0, rs[1] <= 1.0, rs[2] <= 1.0, rs[3] <= 1.0];
+
+        WRay::new(
+            origin,
+            dir.normalized(),
+            time,
+            [tile_coord, tile_coord, tile_coord, tile_coord],
+            valid,
+            [scramble, scramble, scramble, scramble],
+            sample_nums,
+        )
+    }
+
+    fn half_pixel_size_at(&self, t: f32x4) -> f32x4 {
+        self.half_pixel_size * t
+    }
+
+    fn project(&self, point: Wec3, time: f32x4) -> Option<Wec2> {
+        let origin = self.origin.sample_at(time);
+        let at = self.at.sample_at(time);
+        let up = self.up.sample_at(time);
+
+        let basis_w = (origin - at).normalized();
+        let basis_u = up.cross(basis_w).normalized();
+        let basis_v = basis_w.cross(basis_u);
+
+        let dir = (point - origin).normalized();
+        let x = dir.dot(basis_u);
+        let y = dir.dot(basis_v);
+        let theta = map_lanes(-dir.dot(basis_w), |c| c.max(-1.0).min(1.0).acos());
+
+        let r = match self.projection {
+            FisheyeProjection::Equidistant => theta / f32x4::from(self.half_fov),
+            FisheyeProjection::Equisolid => {
+                let k = (self.half_fov / 2.0).sin();
+                map_lanes(theta, |theta| (theta / 2.0).sin() / k)
+            }
+        };
+
+        let sin_theta = (x * x + y * y).sqrt();
+        let inv_sin_theta = f32x4::merge(
+            sin_theta.cmp_gt(f32x4::from(0.000001)),
+            f32x4::ONE / sin_theta,
+            f32x4::ZERO,
+        );
+
+        // points outside the image circle aren't visible
+        let inside = r.cmp_le(f32x4::ONE);
+        let nan = f32x4::from(std::f32::NAN);
+        let half = f32x4::from(0.5);
+        let u = x * inv_sin_theta * r / self.uv_scale.x + half;
+        let v = y * inv_sin_theta * r / self.uv_scale.y + half;
+        Some(Wec2::new(
+            f32x4::merge(inside, u, nan),
+            f32x4::merge(inside, v, nan),
+        ))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoEye {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// Render a single eye, filling the whole film. Use one camera per eye to render
+    /// each eye into its own film.
+    Single(StereoEye),
+    /// Render both eyes into one film, the left eye in the top half and the right eye in
+    /// the bottom half.
+    TopBottom,
+}
+
+/// An omni-directional stereo camera, which renders an equirectangular panorama for each eye
+/// as seen from a viewing circle with the diameter of the interpupillary distance.
+///
+/// Looking straight up or down, there is no consistent stereo baseline, so the eyes are
+/// merged toward the poles starting from `pole_merge_from` degrees of latitude.
+#[derive(Clone, Copy)]
+pub struct OdsCamera<O, A, U> {
+    layout: StereoLayout,
+    half_ipd: f32,
+    // latitude in radians at which the eyes start merging
+    pole_merge_from: f32,
+    // pi / eye resolution.h / 2.0
+    half_pixel_size: f32x4,
+    origin: O,
+    at: A,
+    up: U,
+}
+
+impl<O, A, U> OdsCamera<O, A, U> {
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        resolution: Vec2,
+        layout: StereoLayout,
+        ipd: f32,
+        pole_merge_from: f32,
+        origin: O,
+        at: A,
+        up: U,
+    ) -> Result<Self, String> {
+        // at 90 degrees there would be no latitudes left to merge over
+        if !(0.0..90.0).contains(&pole_merge_from) {
+            return Err(format!(
+                "Pole merge latitude must be at least 0 and less than 90 degrees, got {}",
+                pole_merge_from
+            ));
+        }
+        let eye_height = match layout {
+            StereoLayout::Single(_) => resolution.y,
+            StereoLayout::TopBottom => resolution.y / 2.0,
+        };
+        Ok(OdsCamera {
+            layout,
+            half_ipd: ipd / 2.0,
+            pole_merge_from: pole_merge_from * std::f32::consts::PI / 180.0,
+            half_pixel_size: f32x4::from(std::f32::consts::PI / eye_height / 2.0),
+            origin,
+            at,
+            up,
+        })
+    }
+}
+
+impl<O, A, U> Camera for OdsCamera<O, A, U>
+where
+    O: WSequenced<Wec3>,
+    A: WSequenced<Wec3>,
+    U: WSequenced<Wec3>,
+{
+    fn get_rays(
+        &self,
+        scramble: f32,
+        sample_nums: [usize; 4],
+        tile_coord: Vec2u,
+        uv: Wec2,
+        time: f32x4,
+        _samples: &[f32x4; 2],
+    ) -> WRay {
+        let origin = self.origin.sample_at(time);
+        let at = self.at.sample_at(time);
+        let up = self.up.sample_at(time);
+
+        let basis_w = (origin - at).normalized();
+        let basis_u = up.cross(basis_w).normalized();
+        let basis_v = basis_w.cross(basis_u);
+
+        let half = f32x4::from(0.5);
+        let two = f32x4::from(2.0);
+
+        // -1 for the left eye, 1 for the right eye, and the vertical position within that eye
+        let (eye_sign, v) = match self.layout {
+            StereoLayout::Single(StereoEye::Left) => (-f32x4::ONE, uv.y),
+            StereoLayout::Single(StereoEye::Right) => (f32x4::ONE, uv.y),
+            StereoLayout::TopBottom => {
+                let bottom = uv.y.cmp_lt(half);
+                (
+                    f32x4::merge(bottom, f32x4::ONE, -f32x4::ONE),
+                    f32x4::merge(bottom, uv.y * two, uv.y * two - f32x4::ONE),
+                )
+            }
+        };
+
+        let longitude = (uv.x - half) * f32x4::TWO_PI;
+        let latitude = (v - half) * f32x4::PI;
+        let (sin_long, cos_long) = longitude.sin_cos();
+        let (sin_lat, cos_lat) = latitude.sin_cos();
+
+        let merge_range = f32x4::from(std::f32::consts::FRAC_PI_2 - self.pole_merge_from);
+        let pole_dist = f32x4::FRAC_PI_2 - latitude.abs();
+        let merge = (pole_dist / merge_range).min(f32x4::ONE).max(f32x4::ZERO);
+        let merge = merge * merge * (f32x4::from(3.0) - two * merge);
+
+        let offset = eye_sign * f32x4::from(self.half_ipd) * merge;
+        let origin = origin + (basis_u * cos_long + basis_w * sin_long) * offset;
+
+        let dir =
+            basis_u * (sin_long * cos_lat) + basis_v * sin_lat - basis_w * (cos_long * cos_lat);
+
+        WRay::new(
+            origin,
+            dir.normalized(),
+            time,
+            [tile_coord, tile_coord, tile_coord, tile_coord],
+            [true, true, true, true],
+            [scramble, scramble, scramble, scramble],
+            sample_nums,
+        )
+    }
+
+    fn half_pixel_size_at(&self, t: f32x4) -> f32x4 {
+        self.half_pixel_size * t
+    }
 }
@@ -0,0 +1,235 @@
+//! A simple binary format for saving the state of a render to disk so it can be resumed,
+//! made of little endian values written one after another.
+
+use crate::math::{Vec2, Vec3};
+use crate::spectrum::Srgb;
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+pub const MAGIC: [u8; 8] = *b"RAYNCKPT";
+pub const VERSION: u32 = 6;
+
+/// Values which can be written to and read back from a checkpoint.
+pub trait Checkpointable: Sized {
+    fn write_to(&self, w: &mut CheckpointWriter);
+    fn read_from(r: &mut CheckpointReader) -> Result<Self, String>;
+}
+
+pub struct CheckpointWriter {
+    bytes: Vec<u8>,
+}
+
+impl CheckpointWriter {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        CheckpointWriter { bytes }
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.bytes.push(v);
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, v: f32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn string(&mut self, v: &str) {
+        self.u64(v.len() as u64);
+        self.bytes.extend_from_slice(v.as_bytes());
+    }
+
+    /// Write the checkpoint to a temporary file next to `path` and then move it into place,
+    /// so that a crash while saving never leaves a partial checkpoint behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+
+        let file = File::create(&tmp_path)
+            .map_err(|e| format!("Failed to create checkpoint {}: {}", tmp_path.display(), e))?;
+        let mut writer = BufWriter::new(file);
+        writer
+            .write_all(&self.bytes)
+            .and_then(|_| writer.flush())
+            .map_err(|e| format!("Failed to write checkpoint {}: {}", tmp_path.display(), e))?;
+        drop(writer);
+
+        std::fs::rename(&tmp_path, path)
+            .map_err(|e| format!("Failed to move checkpoint to {}: {}", path.display(), e))
+    }
+}
+
+pub struct CheckpointReader {
+    bytes: Vec<u8>,
+    pos: usize,
+}
+
+impl CheckpointReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("Failed to read checkpoint {}: {}", path.display(), e))?;
+
+        let mut reader = CheckpointReader { bytes, pos: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(format!("{} is not a checkpoint", path.display()));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!(
+                "Checkpoint {} has version {}, expected {}",
+                path.display(),
+                version,
+                VERSION
+            ));
+        }
+        Ok(reader)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        if self.pos + len > self.bytes.len() {
+            return Err(String::from("Checkpoint ended unexpectedly"));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        let b = self.take(8)?;
+        Ok(u64::from_le_bytes([
+            b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+        ]))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String> {
+        let b = self.take(4)?;
+        Ok(f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn string(&mut self) -> Result<String, String> {
+        let len = self.u64()? as usize;
+        let bytes = self.take(len)?.to_vec();
+        String::from_utf8(bytes).map_err(|_| String::from("Invalid string in checkpoint"))
+    }
+}
+
+impl Checkpointable for f32 {
+    fn write_to(&self, w: &mut CheckpointWriter) {
+        w.f32(*self);
+    }
+
+    fn read_from(r: &mut CheckpointReader) -> Result<Self, String> {
+        r.f32()
+    }
+}
+
+impl Checkpointable for Vec2 {
+    fn write_to(&self, w: &mut CheckpointWriter) {
+        w.f32(self.x);
+        w.f32(self.y);
+    }
+
+    fn read_from(r: &mut CheckpointReader) -> Result<Self, String> {
+        Ok(Vec2::new(r.f32()?, r.f32()?))
+    }
+}
+
+impl Checkpointable for Vec3 {
+    fn write_to(&self, w: &mut CheckpointWriter) {
+        w.f32(self.x);
+        w.f32(self.y);
+        w.f32(self.z);
+    }
+
+    fn read_from(r: &mut CheckpointReader) -> Result<Self, String> {
+        Ok(Vec3::new(r.f32()?, r.f32()?, r.f32()?))
+    }
+}
+
+impl Checkpointable for Srgb {
+    fn write_to(&self, w: &mut CheckpointWriter) {
+        w.f32(self.x);
+        w.f32(self.y);
+        w.f32(self.z);
+    }
+
+    fn read_from(r: &mut CheckpointReader) -> Result<Self, String> {
+        Ok(Srgb::new(r.f32()?, r.f32()?, r.f32()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rayn-{}-{}.ckpt", name, std::process::id()))
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round-trip");
+
+        let mut w = CheckpointWriter::new();
+        w.u8(7);
+        w.u64(u64::max_value() - 1);
+        w.f32(-1.5);
+        w.string("pmj02");
+        Vec2::new(0.25, 0.5).write_to(&mut w);
+        Vec3::new(1.0, 2.0, 3.0).write_to(&mut w);
+        w.save(&path).unwrap();
+        assert!(!path.with_extension("tmp").exists());
+
+        let mut r = CheckpointReader::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(r.u8().unwrap(), 7);
+        assert_eq!(r.u64().unwrap(), u64::max_value() - 1);
+        assert_eq!(r.f32().unwrap(), -1.5);
+        assert_eq!(r.string().unwrap(), "pmj02");
+        assert_eq!(Vec2::read_from(&mut r).unwrap(), Vec2::new(0.25, 0.5));
+        assert_eq!(Vec3::read_from(&mut r).unwrap(), Vec3::new(1.0, 2.0, 3.0));
+        assert!(r.u8().is_err());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = temp_path("not-a-checkpoint");
+        std::fs::write(&path, b"P6\n1 1\n255\n\0\0\0").unwrap();
+        let result = CheckpointReader::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let path = temp_path("old-version");
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&(VERSION - 1).to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+        let result = CheckpointReader::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
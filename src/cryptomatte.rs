@@ -0,0 +1,256 @@
+//! Cryptomatte id mattes: each object or material name is hashed to a float id, and every
+//! pixel keeps the ids that cover it ranked by how much of the pixel they cover.
+//! See https://github.com/Psyop/Cryptomatte for the specification.
+
+use crate::checkpoint::{CheckpointReader, CheckpointWriter, Checkpointable};
+use crate::exr::ExrImage;
+
+/// Number of (id, coverage) pairs kept per pixel. Each EXR layer holds two.
+pub const RANKS: usize = 6;
+
+pub fn murmur3_32(bytes: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut h = seed;
+    let mut chunks = bytes.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(C1);
+        k = k.rotate_left(15);
+        k = k.wrapping_mul(C2);
+
+        h ^= k;
+        h = h.rotate_left(13);
+        h = h.wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k = 0u32;
+        for (i, b) in tail.iter().enumerate() {
+            k |= (*b as u32) << (8 * i);
+        }
+        k = k.wrapping_mul(C1);
+        k = k.rotate_left(15);
+        k = k.wrapping_mul(C2);
+        h ^= k;
+    }
+
+    h ^= bytes.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h
+}
+
+/// Hash a name to its id. The hash is turned into a float by reinterpreting its bits, with
+/// the exponent nudged away from denormals, infinities and NaNs.
+pub fn name_id(name: &str) -> f32 {
+    let mut hash = murmur3_32(name.as_bytes(), 0);
+    let exponent = (hash >> 23) & 0xff;
+    if exponent == 0 || exponent == 0xff {
+        hash ^= 1 << 23;
+    }
+    f32::from_bits(hash)
+}
+
+/// The (id, weight) pairs for a pixel, along with the total weight of the samples taken in
+/// it, which the pairs are divided by to get their coverage. Keeping the total here rather
+/// than relying on the film's sample counts means coverage always sums to at most one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coverage {
+    pairs: [(f32, f32); RANKS],
+    total: f32,
+}
+
+impl Coverage {
+    pub fn empty() -> Self {
+        Coverage {
+            pairs: [(0.0, 0.0); RANKS],
+            total: 0.0,
+        }
+    }
+
+    /// A single sample fully covered by `id`.
+    pub fn new(id: f32) -> Self {
+        let mut coverage = Self::empty();
+        coverage.pairs[0] = (id, 1.0);
+        coverage.total = 1.0;
+        coverage
+    }
+
+    /// The (id, coverage) pairs sorted by descending coverage.
+    pub fn ranked(&self) -> [(f32, f32); RANKS] {
+        let mut ranked = self.pairs;
+        for entry in ranked.iter_mut() {
+            entry.1 = if self.total != 0.0 {
+                entry.1 / self.total
+            } else {
+                0.0
+            };
+        }
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        ranked
+    }
+}
+
+impl std::ops::AddAssign for Coverage {
+    fn add_assign(&mut self, other: Self) {
+        self.total += other.total;
+        for &(id, weight) in other.pairs.iter().filter(|(_, weight)| *weight > 0.0) {
+            if let Some(entry) = self.pairs.iter_mut().find(|(this_id, this_weight)| {
+                *this_weight > 0.0 && this_id.to_bits() == id.to_bits()
+            }) {
+                entry.1 += weight;
+                continue;
+            }
+
+            // take an empty slot, or else drop whichever id covers the least
+            let (min_idx, min_weight) = self
+                .pairs
+                .iter()
+                .enumerate()
+                .map(|(i, (_, w))| (i, *w))
+                .fold(
+                    (0, std::f32::MAX),
+                    |min, cur| if cur.1 < min.1 { cur } else { min },
+                );
+            if weight > min_weight {
+                self.pairs[min_idx] = (id, weight);
+            }
+        }
+    }
+}
+
+impl std::ops::Mul<f32> for Coverage {
+    type Output = Self;
+
+    fn mul(mut self, weight: f32) -> Self {
+        for entry in self.pairs.iter_mut() {
+            entry.1 *= weight;
+        }
+        self.total *= weight;
+        self
+    }
+}
+
+impl std::ops::Div<f32> for Coverage {
+    type Output = Self;
+
+    fn div(mut self, samples: f32) -> Self {
+        for entry in self.pairs.iter_mut() {
+            entry.1 /= samples;
+        }
+        self.total /= samples;
+        self
+    }
+}
+
+impl Checkpointable for Coverage {
+    fn write_to(&self, w: &mut CheckpointWriter) {
+        for &(id, weight) in self.pairs.iter() {
+            w.f32(id);
+            w.f32(weight);
+        }
+        w.f32(self.total);
+    }
+
+    fn read_from(r: &mut CheckpointReader) -> Result<Self, String> {
+        let mut coverage = Self::empty();
+        for entry in coverage.pairs.iter_mut() {
+            *entry = (r.f32()?, r.f32()?);
+        }
+        coverage.total = r.f32()?;
+        Ok(coverage)
+    }
+}
+
+/// Write the ranked coverage of a top-down `buf` into `exr` as `layer`00, `layer`01, ...
+/// along with the metadata that maps ids back to `names`.
+pub fn add_layers(exr: &mut ExrImage, layer: &str, buf: &[Coverage], names: &[String]) {
+    let ranked = buf.iter().map(|c| c.ranked()).collect::<Vec<_>>();
+
+    for sublayer in 0..RANKS / 2 {
+        let channel = |rank: usize, coverage: bool| {
+            ranked
+                .iter()
+                .map(|pairs| {
+                    let (id, weight) = pairs[rank];
+                    if coverage {
+                        weight
+                    } else {
+                        id
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        let name = format!("{}{:02}", layer, sublayer);
+        exr.add_channel(format!("{}.R", name), channel(sublayer * 2, false));
+        exr.add_channel(format!("{}.G", name), channel(sublayer * 2, true));
+        exr.add_channel(format!("{}.B", name), channel(sublayer * 2 + 1, false));
+        exr.add_channel(format!("{}.A", name), channel(sublayer * 2 + 1, true));
+    }
+
+    let key = format!("{:08x}", murmur3_32(layer.as_bytes(), 0));
+    let prefix = format!("cryptomatte/{}", &key[0..7]);
+    exr.add_string_attribute(format!("{}/name", prefix), layer);
+    exr.add_string_attribute(format!("{}/hash", prefix), "MurmurHash3_32");
+    exr.add_string_attribute(format!("{}/conversion", prefix), "uint32_to_float32");
+    exr.add_string_attribute(format!("{}/manifest", prefix), manifest(names));
+}
+
+/// A JSON object mapping each name to its hash as hex.
+fn manifest(names: &[String]) -> String {
+    let entries = names
+        .iter()
+        .map(|name| {
+            let escaped = name.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("\"{}\":\"{:08x}\"", escaped, name_id(name).to_bits())
+        })
+        .collect::<Vec<_>>();
+    format!("{{{}}}", entries.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn murmur3_matches_reference() {
+        assert_eq!(murmur3_32(b"", 0), 0);
+        assert_eq!(murmur3_32(b"", 1), 0x514e_28b7);
+        assert_eq!(murmur3_32(b"test", 0), 0xba6b_d213);
+        assert_eq!(murmur3_32(b"Hello, world!", 0x9747_b28c), 0x2488_4cba);
+        assert_eq!(
+            murmur3_32(b"The quick brown fox jumps over the lazy dog", 0x9747_b28c),
+            0x2fa8_26cd
+        );
+    }
+
+    #[test]
+    fn name_ids_match_specification() {
+        // from the example manifest in the Cryptomatte specification
+        assert_eq!(name_id("bunny").to_bits(), 0x1385_1a76);
+        assert_eq!(name_id("default").to_bits(), 0x42c9_679f);
+        assert_eq!(
+            manifest(&[String::from("bunny"), String::from("default")]),
+            r#"{"bunny":"13851a76","default":"42c9679f"}"#
+        );
+    }
+
+    #[test]
+    fn name_ids_are_finite_and_normal() {
+        // these hash to a zero and an all ones exponent
+        assert_eq!(murmur3_32(b"obj45", 0), 0x0073_4d5c);
+        assert_eq!(name_id("obj45").to_bits(), 0x00f3_4d5c);
+        assert_eq!(murmur3_32(b"obj520", 0), 0x7fbb_01ed);
+        assert_eq!(name_id("obj520").to_bits(), 0x7f3b_01ed);
+
+        for i in 0..10000 {
+            assert!(name_id(&format!("obj{}", i)).is_normal());
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Edge-avoiding à-trous wavelet denoising, after Dammertz et al. 2010,
+//! "Edge-Avoiding À-Trous Wavelet Transform for fast Global Illumination Filtering".
+//!
+//! Each iteration blurs the image with a 5x5 B3 spline kernel whose taps are spread
+//! `2^iteration` pixels apart, so a few iterations cover a wide footprint cheaply. Taps are
+//! weighted down where the color or the feature buffers (normal, albedo and depth) differ
+//! from the center pixel, which keeps geometric and texture edges sharp.
+//!
+//! Fireflies are too bright for the edge-stopping weights to blur away, so they are best
+//! removed with `reject_outliers` first.
+
+use crate::math::{Extent2u, Vec3};
+use crate::spectrum::Srgb;
+
+use rayon::prelude::*;
+
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+#[derive(Clone, Copy, Debug)]
+pub struct Denoiser {
+    pub iterations: usize,
+    /// How quickly taps of a different color are rejected. Halved each iteration, since
+    /// the noise left to remove gets smaller as the image is smoothed.
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    pub sigma_albedo: f32,
+    /// Relative to the center pixel's depth.
+    pub sigma_depth: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 5,
+            sigma_color: 0.5,
+            sigma_normal: 0.1,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+/// Feature buffers guiding the denoiser, laid out like the color buffer. Any that are
+/// missing are simply not used to stop the filter at edges.
+#[derive(Default)]
+pub struct Features<'a> {
+    pub normal: Option<&'a [Vec3]>,
+    pub albedo: Option<&'a [Srgb]>,
+    pub depth: Option<&'a [f32]>,
+}
+
+impl Denoiser {
+    pub fn denoise(&self, color: &[Srgb], features: &Features, res: Extent2u) -> Vec<Srgb> {
+        let mut current = color.to_vec();
+        let mut next = vec![Srgb::zero(); color.len()];
+
+        for iteration in 0..self.iterations {
+            let step = 1isize << iteration;
+            let sigma_color = self.sigma_color / (1 << iteration) as f32;
+
+            next.par_chunks_mut(res.w).enumerate().for_each(|(y, row)| {
+                for (x, out) in row.iter_mut().enumerate() {
+                    let p = x + y * res.w;
+                    let color_p = compress(current[p]);
+
+                    let mut sum = Srgb::zero();
+                    let mut weight_sum = 0.0;
+
+                    for (ky, ky_weight) in KERNEL.iter().enumerate() {
+                        let qy = y as isize + (ky as isize - 2) * step;
+                        if qy < 0 || qy >= res.h as isize {
+                            continue;
+                        }
+                        for (kx, kx_weight) in KERNEL.iter().enumerate() {
+                            let qx = x as isize + (kx as isize - 2) * step;
+                            if qx < 0 || qx >= res.w as isize {
+                                continue;
+                            }
+                            let q = qx as usize + qy as usize * res.w;
+
+                            let color_dist = (compress(current[q]).0 - color_p.0).mag_sq();
+                            let mut exponent = color_dist / (sigma_color * sigma_color);
+
+                            if let Some(normal) = features.normal {
+                                let dist = (normal[q] - normal[p]).mag_sq();
+                                exponent += dist / (self.sigma_normal * self.sigma_normal);
+                            }
+                            if let Some(albedo) = features.albedo {
+                                let dist = (albedo[q].0 - albedo[p].0).mag_sq();
+                                exponent += dist / (self.sigma_albedo * self.sigma_albedo);
+                            }
+                            if let Some(depth) = features.depth {
+                                let dist = (depth[q] - depth[p]).abs()
+                                    / (self.sigma_depth * depth[p].max(1e-4));
+                                exponent += dist;
+                            }
+
+                            let weight = ky_weight * kx_weight * (-exponent).exp();
+                            sum += current[q] * weight;
+                            weight_sum += weight;
+                        }
+                    }
+
+                    // the center tap always has a weight of at least 9/64
+                    *out = sum / weight_sum;
+                }
+            });
+
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        current
+    }
+}
+
+/// Compare colors after compressing their range, so that differences in bright areas don't
+/// stop the filter entirely.
+fn compress(color: Srgb) -> Srgb {
+    Srgb::from(color.0 / (Vec3::one() + color.0))
+}
+
+/// Replace isolated bright pixels, whose luminance is more than `sigmas` standard deviations
+/// above the mean of their 8 neighbors, with the average color of those neighbors.
+pub fn reject_outliers(color: &[Srgb], res: Extent2u, sigmas: f32) -> Vec<Srgb> {
+    let mut output = color.to_vec();
+
+    output
+        .par_chunks_mut(res.w)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, out) in row.iter_mut().enumerate() {
+                let p = x + y * res.w;
+
+                let mut neighbors = 0;
+                let mut color_sum = Srgb::zero();
+                let mut lum_sum = 0.0;
+                let mut lum_sq_sum = 0.0;
+                for qy in y.saturating_sub(1)..(y + 2).min(res.h) {
+                    for qx in x.saturating_sub(1)..(x + 2).min(res.w) {
+                        let q = qx + qy * res.w;
+                        if q == p {
+                            continue;
+                        }
+                        let lum = color[q].luminance();
+                        neighbors += 1;
+                        color_sum += color[q];
+                        lum_sum += lum;
+                        lum_sq_sum += lum * lum;
+                    }
+                }
+                if neighbors == 0 {
+                    continue;
+                }
+
+                let mean = lum_sum / neighbors as f32;
+                let std_dev = (lum_sq_sum / neighbors as f32 - mean * mean)
+                    .max(0.0)
+                    .sqrt();
+                // a flat neighborhood would otherwise flag any pixel slightly brighter than it
+                let limit = mean + sigmas * std_dev.max(mean * 0.1).max(1e-3);
+                if color[p].luminance() > limit {
+                    *out = color_sum / neighbors as f32;
+                }
+            }
+        });
+
+    output
+}
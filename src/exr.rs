@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+// single part scanline image, no long names
+const VERSION: u32 = 2;
+const PIXEL_TYPE_FLOAT: i32 = 2;
+
+/// A minimal OpenEXR writer: an uncompressed scanline image of 32 bit float channels, with
+/// optional extra string attributes in the header.
+pub struct ExrImage {
+    width: usize,
+    height: usize,
+    channels: Vec<(String, Vec<f32>)>,
+    attributes: Vec<(String, String)>,
+}
+
+impl ExrImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        ExrImage {
+            width,
+            height,
+            channels: Vec::new(),
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Add a channel, with `data` in rows from the top of the image down.
+    pub fn add_channel<S: Into<String>>(&mut self, name: S, data: Vec<f32>) {
+        assert_eq!(
+            data.len(),
+            self.width * self.height,
+            "EXR channel data does not match the image size"
+        );
+        self.channels.push((name.into(), data));
+    }
+
+    pub fn add_string_attribute<S: Into<String>, V: Into<String>>(&mut self, name: S, value: V) {
+        self.attributes.push((name.into(), value.into()));
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let file = File::create(path.as_ref()).map_err(|e| {
+            format!(
+                "Failed to create EXR file {}: {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+        let mut writer = BufWriter::new(file);
+        self.write(&mut writer)
+            .and_then(|_| writer.flush())
+            .map_err(|e| {
+                format!(
+                    "Failed to write EXR file {}: {}",
+                    path.as_ref().display(),
+                    e
+                )
+            })
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        // readers require channels to be sorted by name
+        let mut channels = self.channels.iter().collect::<Vec<_>>();
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+
+        let mut chlist = Vec::new();
+        for (name, _) in channels.iter() {
+            chlist.extend_from_slice(name.as_bytes());
+            chlist.push(0);
+            chlist.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+            // pLinear and reserved bytes
+            chlist.extend_from_slice(&[0, 0, 0, 0]);
+            // x and y sampling
+            chlist.extend_from_slice(&1i32.to_le_bytes());
+            chlist.extend_from_slice(&1i32.to_le_bytes());
+        }
+        chlist.push(0);
+        write_attribute(&mut header, "channels", "chlist", &chlist);
+
+        // no compression
+        write_attribute(&mut header, "compression", "compression", &[0]);
+
+        let mut window = Vec::new();
+        for v in [0, 0, self.width as i32 - 1, self.height as i32 - 1].iter() {
+            window.extend_from_slice(&v.to_le_bytes());
+        }
+        write_attribute(&mut header, "dataWindow", "box2i", &window);
+        write_attribute(&mut header, "displayWindow", "box2i", &window);
+
+        // increasing y
+        write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        write_attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+
+        let mut center = Vec::new();
+        center.extend_from_slice(&0f32.to_le_bytes());
+        center.extend_from_slice(&0f32.to_le_bytes());
+        write_attribute(&mut header, "screenWindowCenter", "v2f", &center);
+        write_attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+
+        for (name, value) in self.attributes.iter() {
+            write_attribute(&mut header, name, "string", value.as_bytes());
+        }
+        header.push(0);
+
+        w.write_all(&header)?;
+
+        // one scanline per chunk: y coordinate, data size, then each channel's row
+        let row_size = channels.len() * self.width * 4;
+        let chunk_size = 8 + row_size;
+        let offsets_start = header.len() + self.height * 8;
+        for y in 0..self.height {
+            let offset = (offsets_start + y * chunk_size) as u64;
+            w.write_all(&offset.to_le_bytes())?;
+        }
+
+        let mut row = Vec::with_capacity(row_size);
+        for y in 0..self.height {
+            row.clear();
+            for (_, data) in channels.iter() {
+                for v in data[y * self.width..(y + 1) * self.width].iter() {
+                    row.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+            w.write_all(&(y as i32).to_le_bytes())?;
+            w.write_all(&(row_size as i32).to_le_bytes())?;
+            w.write_all(&row)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
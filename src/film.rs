@@ -1,16 +1,21 @@
 use bumpalo::{collections::Vec as BumpVec, Bump};
 
-use generic_array::{ArrayLength, GenericArray};
-
 use rand::prelude::*;
 
-use crate::camera::CameraHandle;
+use crate::blue_noise::BlueNoiseMask;
+use crate::camera::{Camera, CameraHandle};
+use crate::checkpoint::{CheckpointReader, CheckpointWriter, Checkpointable};
+use crate::cryptomatte::{self, Coverage};
+use crate::denoise::{self, Denoiser, Features};
+use crate::exr::ExrImage;
 use crate::filter::{Filter, FilterImportanceSampler};
-use crate::hitable::HitStore;
+use crate::hitable::{HitStore, HitableHandle, WShadingPoint};
 use crate::integrator::Integrator;
 use crate::math::{f32x4, Aabru, Extent2u, Vec2, Vec2u, Vec3, Wec2};
-use crate::ray::{Ray, WRay};
-use crate::sampler::Samples;
+use crate::post::PostEffect;
+use crate::ray::{Ray, SampleCoord, WRay};
+use crate::sampler::{AnySampler, Samples};
+use crate::shutter::Shutter;
 use crate::spectrum::Srgb;
 use crate::world::World;
 
@@ -23,19 +28,27 @@ use std::sync::{
 
 macro_rules! declare_channels {
     {
-        $($name:ident => {
+        $($name:ident $(($param:ident: $param_ty:ty))? => {
             storage: $storage:ident,
             init: $initialize:expr,
         }),+
     } => {
         #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
         pub enum ChannelKind {
-            $($name,)+
+            $($name $(($param_ty))?,)+
         }
 
         #[derive(Debug)]
         pub enum ChannelSample {
-            $($name($storage),)+
+            $($name($($param_ty,)? $storage),)+
+        }
+
+        impl ChannelSample {
+            pub fn kind(&self) -> ChannelKind {
+                match *self {
+                    $( ChannelSample::$name($($param,)? _) => ChannelKind::$name$(($param))?, )+
+                }
+            }
         }
 
         pub enum ChannelTileStorage {
@@ -44,50 +57,96 @@ macro_rules! declare_channels {
 
         impl ChannelTileStorage {
             fn new(kind: ChannelKind, res: Extent2u) -> Self {
-                use ChannelKind::*;
                 match kind {
-                    $($name => Self::$name(vec![($initialize); res.w * res.h]),)+
+                    $(ChannelKind::$name { .. } => Self::$name(vec![($initialize); res.w * res.h]),)+
                 }
             }
 
             fn add_sample(&mut self, idx: usize, sample: &ChannelSample) {
                 match (self, sample) {
-                    $((ChannelTileStorage::$name(ref mut buf), ChannelSample::$name(sample)) => {
+                    $((ChannelTileStorage::$name(ref mut buf), ChannelSample::$name(.., sample)) => {
                         buf[idx] += *sample;
                     },)+
                     _ => (),
                 }
             }
+
+            fn add_weighted_sample(&mut self, idx: usize, sample: &ChannelSample, weight: f32) {
+                match (self, sample) {
+                    $((ChannelTileStorage::$name(ref mut buf), ChannelSample::$name(.., sample)) => {
+                        buf[idx] += *sample * weight;
+                    },)+
+                    _ => (),
+                }
+            }
         }
 
         pub enum ChannelStorage {
-            $($name(Vec<$storage>),)+
+            $($name($($param_ty,)? Vec<$storage>),)+
         }
 
         impl ChannelStorage {
             fn new(kind: ChannelKind, res: Extent2u) -> Self {
-                use ChannelKind::*;
                 match kind {
-                    $($name => Self::$name(vec![$initialize; res.w * res.h]),)+
+                    $(ChannelKind::$name$(($param))? => Self::$name($($param,)? vec![$initialize; res.w * res.h]),)+
                 }
             }
 
             pub fn kind(&self) -> ChannelKind {
                 match *self {
-                    $( ChannelStorage::$name(_) => ChannelKind::$name, )+
+                    $( ChannelStorage::$name($($param,)? _) => ChannelKind::$name$(($param))?, )+
                 }
             }
 
-            pub fn copy_from_tile(&mut self, other: &ChannelTileStorage, full_res: Extent2u, tile_bounds: Aabru, samples: usize) -> Result<(), ()> {
+            /// Average a tile's sums of `samples` new samples in each of its pixels into this
+            /// channel, which already holds the average of `prev_samples` samples per pixel.
+            pub fn accumulate_from_tile(&mut self, other: &ChannelTileStorage, full_res: Extent2u, tile_bounds: Aabru, prev_samples: &[usize], samples: &[usize]) -> Result<(), ()> {
                 let extent = tile_bounds.size();
                 match (self, other) {
-                    $( (ChannelStorage::$name(this_buf), ChannelTileStorage::$name(tile_buf)) => {
+                    $( (ChannelStorage::$name(.., this_buf), ChannelTileStorage::$name(tile_buf)) => {
                         for x in 0..extent.w {
                             for y in 0..extent.h {
                                 let tile_idx = x + y * extent.w;
                                 let this_idx = (tile_bounds.min.x + x) + (tile_bounds.min.y + y) * full_res.w;
+                                let prev = prev_samples[this_idx];
+                                let total = prev + samples[tile_idx];
+                                if total == 0 {
+                                    continue;
+                                }
                                 let tile_samp_sum = tile_buf[tile_idx];
-                                this_buf[this_idx] = tile_samp_sum / samples as f32;
+                                let mut samp_sum = if prev > 0 {
+                                    this_buf[this_idx] * prev as f32
+                                } else {
+                                    $initialize
+                                };
+                                samp_sum += tile_samp_sum;
+                                this_buf[this_idx] = samp_sum / total as f32;
+                            }
+                        }
+                        Ok(())
+                    }, )+
+                    _ => Err(())
+                }
+            }
+
+            /// Add the weighted sums splatted into a tile, including the `padding` pixels
+            /// around its bounds that overlap neighboring tiles, onto this channel.
+            pub fn add_from_tile(&mut self, other: &ChannelTileStorage, full_res: Extent2u, tile_bounds: Aabru, padding: usize) -> Result<(), ()> {
+                let extent = tile_bounds.size();
+                let padded_w = extent.w + padding * 2;
+                let padded_h = extent.h + padding * 2;
+                match (self, other) {
+                    $( (ChannelStorage::$name(.., this_buf), ChannelTileStorage::$name(tile_buf)) => {
+                        for x in 0..padded_w {
+                            for y in 0..padded_h {
+                                let film_x = (tile_bounds.min.x + x).wrapping_sub(padding);
+                                let film_y = (tile_bounds.min.y + y).wrapping_sub(padding);
+                                if film_x >= full_res.w || film_y >= full_res.h {
+                                    continue;
+                                }
+                                let tile_idx = x + y * padded_w;
+                                let this_idx = film_x + film_y * full_res.w;
+                                this_buf[this_idx] += tile_buf[tile_idx];
                             }
                         }
                         Ok(())
@@ -95,6 +154,64 @@ macro_rules! declare_channels {
                     _ => Err(())
                 }
             }
+
+            /// Divide each pixel's weighted sum by the sum of its filter weights.
+            pub fn normalize(&mut self, weights: &[f32]) {
+                match self {
+                    $( ChannelStorage::$name(.., buf) => {
+                        for (value, weight) in buf.iter_mut().zip(weights.iter()) {
+                            *value = if *weight != 0.0 {
+                                *value / *weight
+                            } else {
+                                $initialize
+                            };
+                        }
+                    }, )+
+                }
+            }
+
+            /// Undo `normalize`, turning each pixel back into its weighted sum so that more
+            /// samples can be splatted onto it.
+            pub fn denormalize(&mut self, weights: &[f32]) {
+                match self {
+                    $( ChannelStorage::$name(.., buf) => {
+                        for (value, weight) in buf.iter_mut().zip(weights.iter()) {
+                            *value = *value * *weight;
+                        }
+                    }, )+
+                }
+            }
+
+            pub fn write_checkpoint(&self, w: &mut CheckpointWriter) {
+                match self {
+                    $( ChannelStorage::$name(.., buf) => {
+                        for value in buf.iter() {
+                            value.write_to(w);
+                        }
+                    }, )+
+                }
+            }
+
+            pub fn read_checkpoint(&mut self, r: &mut CheckpointReader) -> Result<(), String> {
+                match self {
+                    $( ChannelStorage::$name(.., buf) => {
+                        for value in buf.iter_mut() {
+                            *value = <$storage as Checkpointable>::read_from(r)?;
+                        }
+                    }, )+
+                }
+                Ok(())
+            }
+
+            pub fn clear(&mut self) {
+                match self {
+                    $( ChannelStorage::$name(.., buf) => {
+                        for value in buf.iter_mut() {
+                            *value = $initialize;
+                        }
+                    }, )+
+                }
+            }
         }
     }
 }
@@ -115,12 +232,239 @@ declare_channels! {
     WorldNormal => {
         storage: Vec3,
         init: Vec3::zero(),
+    },
+    // distance from the camera to the first hit, which for the sky sphere is about the
+    // world's radius
+    Depth => {
+        storage: f32,
+        init: 0f32,
+    },
+    WorldPosition => {
+        storage: Vec3,
+        init: Vec3::zero(),
+    },
+    MotionVector => {
+        storage: Motion,
+        init: Motion::none(),
+    },
+    Albedo => {
+        storage: Srgb,
+        init: Srgb::zero(),
+    },
+    MaterialId => {
+        storage: Id,
+        init: Id::none(),
+    },
+    ObjectId => {
+        storage: Id,
+        init: Id::none(),
+    },
+    CryptoObject => {
+        storage: Coverage,
+        init: Coverage::empty(),
+    },
+    CryptoMaterial => {
+        storage: Coverage,
+        init: Coverage::empty(),
+    },
+    LightGroup(group: usize) => {
+        storage: Srgb,
+        init: Srgb::zero(),
+    },
+    Direct => {
+        storage: Srgb,
+        init: Srgb::zero(),
+    },
+    Indirect => {
+        storage: Srgb,
+        init: Srgb::zero(),
+    },
+    Diffuse => {
+        storage: Srgb,
+        init: Srgb::zero(),
+    },
+    Specular => {
+        storage: Srgb,
+        init: Srgb::zero(),
+    },
+    FilterWeight => {
+        storage: f32,
+        init: 0f32,
+    },
+    SampleCount => {
+        storage: f32,
+        init: 0f32,
+    }
+}
+
+/// Keep sampling pixels whose estimated relative error is above `threshold`, for up to
+/// `max_rounds` extra rounds of samples after the first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    pub threshold: f32,
+    pub max_rounds: usize,
+}
+
+/// How samples are reconstructed into pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reconstruction {
+    /// Sample positions are distributed according to the filter and each sample only
+    /// counts toward its own pixel. Fast, but the filter must not have any negative lobe.
+    ImportanceSampled,
+    /// Samples are spread uniformly over their pixel and splatted into every pixel within
+    /// the filter's radius, weighted by the filter. Pixels are divided by the sum of their
+    /// weights, kept in the `FilterWeight` channel, so filters with negative lobes work.
+    Splatted,
+}
+
+/// An id sample, such as a material or object index. Ids can't be averaged, so the first
+/// sample taken in a pixel is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Id(Option<usize>);
+
+impl Id {
+    pub fn new(id: usize) -> Self {
+        Id(Some(id))
+    }
+
+    pub fn none() -> Self {
+        Id(None)
+    }
+
+    /// Id as a float for image output, with -1 where there was none.
+    pub fn as_f32(self) -> f32 {
+        self.0.map(|id| id as f32).unwrap_or(-1.0)
+    }
+
+    /// A color unique to each id for previewing, black where there was none.
+    pub fn false_color(self) -> Srgb {
+        match self.0 {
+            Some(id) => {
+                // spread consecutive ids around the hue circle
+                let hash = (id as u32).wrapping_add(1).wrapping_mul(0x9E37_79B9);
+                let channel = |shift: u32| 0.2 + 0.8 * ((hash >> shift) & 0xff) as f32 / 255.0;
+                Srgb::new(channel(0), channel(8), channel(16))
+            }
+            None => Srgb::zero(),
+        }
+    }
+}
+
+impl std::ops::AddAssign for Id {
+    fn add_assign(&mut self, other: Self) {
+        if self.0.is_none() {
+            self.0 = other.0;
+        }
+    }
+}
+
+impl std::ops::Mul<f32> for Id {
+    type Output = Self;
+
+    fn mul(self, _weight: f32) -> Self {
+        self
+    }
+}
+
+impl std::ops::Div<f32> for Id {
+    type Output = Self;
+
+    fn div(self, _samples: f32) -> Self {
+        self
+    }
+}
+
+impl Checkpointable for Id {
+    fn write_to(&self, w: &mut CheckpointWriter) {
+        w.u64(self.0.map(|id| id as u64 + 1).unwrap_or(0));
+    }
+
+    fn read_from(r: &mut CheckpointReader) -> Result<Self, String> {
+        let id = r.u64()?;
+        Ok(Id(id.checked_sub(1).map(|id| id as usize)))
+    }
+}
+
+/// A motion vector sample. Not every sample can be projected at both ends of the shutter,
+/// so each pixel keeps the sum of its weights alongside the weighted sum of its vectors and
+/// is averaged over only the samples that contributed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Motion {
+    sum: Vec2,
+    weight: f32,
+}
+
+impl Motion {
+    pub fn new(pixels: Vec2) -> Self {
+        Motion {
+            sum: pixels,
+            weight: 1.0,
+        }
+    }
+
+    pub fn none() -> Self {
+        Motion {
+            sum: Vec2::zero(),
+            weight: 0.0,
+        }
+    }
+
+    /// The average motion in pixels, or zero where there was none.
+    pub fn pixels(self) -> Vec2 {
+        if self.weight != 0.0 {
+            self.sum / self.weight
+        } else {
+            Vec2::zero()
+        }
+    }
+}
+
+impl std::ops::AddAssign for Motion {
+    fn add_assign(&mut self, other: Self) {
+        self.sum += other.sum;
+        self.weight += other.weight;
+    }
+}
+
+impl std::ops::Mul<f32> for Motion {
+    type Output = Self;
+
+    fn mul(self, weight: f32) -> Self {
+        Motion {
+            sum: self.sum * weight,
+            weight: self.weight * weight,
+        }
+    }
+}
+
+impl std::ops::Div<f32> for Motion {
+    type Output = Self;
+
+    fn div(self, samples: f32) -> Self {
+        Motion {
+            sum: self.sum / samples,
+            weight: self.weight / samples,
+        }
+    }
+}
+
+impl Checkpointable for Motion {
+    fn write_to(&self, w: &mut CheckpointWriter) {
+        self.sum.write_to(w);
+        w.f32(self.weight);
+    }
+
+    fn read_from(r: &mut CheckpointReader) -> Result<Self, String> {
+        Ok(Motion {
+            sum: Vec2::read_from(r)?,
+            weight: r.f32()?,
+        })
     }
 }
 
 macro_rules! channel_storage_index {
     ($storage:expr, $channel:ident, $idx:expr) => {
-        if let ChannelStorage::$channel(x) = &$storage[$idx] {
+        if let ChannelStorage::$channel(.., x) = &$storage[$idx] {
             x
         } else {
             panic!("Attempted to index into channel storage array with wrong channel type.");
@@ -128,58 +472,212 @@ macro_rules! channel_storage_index {
     };
 }
 
-pub struct Tile<N: ArrayLength<ChannelTileStorage>> {
+pub struct Tile {
     _index: usize,
     epoch: usize,
-    channels: GenericArray<ChannelTileStorage, N>,
+    channels: Vec<ChannelTileStorage>,
+    channel_kinds: Vec<ChannelKind>,
     raster_bounds: Aabru,
-    raster_extent: Extent2u,
+    reconstruction: Reconstruction,
+    // pixels kept around the tile's bounds for samples splatted into neighboring tiles
+    padding: usize,
+    storage_extent: Extent2u,
     screen_to_ndc_size: Vec2,
+    // camera samples taken in each pixel by this tile, and by every earlier pass
+    sample_counts: Vec<usize>,
+    prior_counts: Vec<usize>,
+    // sums of the luminances and squared luminances of every sample in each pixel, starting
+    // from the film's, for estimating how noisy each pixel still is
+    luminance_sums: Vec<(f32, f32)>,
 }
 
-impl<N: ArrayLength<ChannelTileStorage>> Tile<N> {
+impl Tile {
     pub fn new<IC>(
         _index: usize,
         epoch: usize,
         channels: IC,
         res: Extent2u,
         raster_bounds: Aabru,
+        reconstruction: Reconstruction,
+        padding: usize,
+        pixel_samples: &[usize],
+        luminance_sums: &[(f32, f32)],
     ) -> Self
     where
         IC: std::iter::ExactSizeIterator<Item = ChannelKind>,
     {
         let screen_to_ndc_size = Vec2::new(1.0 / res.w as f32, 1.0 / res.h as f32);
+        let channel_kinds = channels.collect::<Vec<_>>();
+        let raster_extent = raster_bounds.size();
+        let pixels = raster_extent.w * raster_extent.h;
+        let storage_extent =
+            Extent2u::new(raster_extent.w + padding * 2, raster_extent.h + padding * 2);
+
+        // copy the film's statistics for the tile's pixels, in the tile's order
+        let film_indices = (0..pixels).map(|i| {
+            let x = raster_bounds.min.x + i % raster_extent.w;
+            let y = raster_bounds.min.y + i / raster_extent.w;
+            x + y * res.w
+        });
 
         Tile {
             _index,
             epoch,
-            channels: GenericArray::from_exact_iter(
-                channels.map(|kind| ChannelTileStorage::new(kind, raster_bounds.size())),
-            )
-            .expect("Incorrect number of channels passed to tile creation"),
+            channels: channel_kinds
+                .iter()
+                .map(|kind| ChannelTileStorage::new(*kind, storage_extent))
+                .collect(),
+            channel_kinds,
             raster_bounds,
-            raster_extent: raster_bounds.size(),
+            reconstruction,
+            padding,
+            storage_extent,
             screen_to_ndc_size,
+            sample_counts: vec![0; pixels],
+            prior_counts: film_indices.clone().map(|i| pixel_samples[i]).collect(),
+            luminance_sums: film_indices.map(|i| luminance_sums[i]).collect(),
         }
     }
 
-    pub fn add_sample(&mut self, tile_coord: Vec2u, sample: ChannelSample) {
-        let idx = tile_coord.x + tile_coord.y * self.raster_extent.w;
-        for channel in self.channels.iter_mut() {
-            channel.add_sample(idx, &sample);
+    fn pixel_index(&self, pixel: Vec2u) -> usize {
+        pixel.x + pixel.y * self.raster_bounds.size().w
+    }
+
+    /// Count a camera sample taken at `coord`.
+    pub fn add_camera_sample<F: Filter>(&mut self, coord: SampleCoord, filter: &F) {
+        let idx = self.pixel_index(coord.pixel);
+        self.sample_counts[idx] += 1;
+        self.add_sample(coord, ChannelSample::FilterWeight(1.0), filter);
+    }
+
+    /// Whether the pixel at `pixel` is still noisier than `threshold`, estimated from the
+    /// standard error of the luminance of all of its samples so far relative to their mean.
+    pub fn needs_samples(&self, pixel: Vec2u, threshold: f32) -> bool {
+        let idx = self.pixel_index(pixel);
+        let n = (self.prior_counts[idx] + self.sample_counts[idx]) as f32;
+        if n < 2.0 {
+            return true;
+        }
+        let (sum, sq_sum) = self.luminance_sums[idx];
+        let mean = sum / n;
+        let variance = ((sq_sum / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        let std_error = (variance / n).sqrt();
+        // don't chase noise in pixels that are nearly black
+        std_error / mean.max(0.01) > threshold
+    }
+
+    pub fn add_sample<F: Filter>(&mut self, coord: SampleCoord, sample: ChannelSample, filter: &F) {
+        // each camera sample ends in exactly one Color or Background sample
+        let luminance = match sample {
+            ChannelSample::Color(color) => Some(color.luminance()),
+            ChannelSample::Background(color) => Some(color.luminance()),
+            _ => None,
+        };
+        if let Some(luminance) = luminance {
+            let idx = self.pixel_index(coord.pixel);
+            self.luminance_sums[idx].0 += luminance;
+            self.luminance_sums[idx].1 += luminance * luminance;
+        }
+
+        match self.reconstruction {
+            Reconstruction::ImportanceSampled => self.add_to_pixel(coord, sample),
+            Reconstruction::Splatted => self.splat_sample(coord, sample, filter),
+        }
+    }
+
+    fn add_to_pixel(&mut self, coord: SampleCoord, sample: ChannelSample) {
+        let x = coord.pixel.x + self.padding;
+        let y = coord.pixel.y + self.padding;
+        let idx = x + y * self.storage_extent.w;
+        let kind = sample.kind();
+        for (channel, channel_kind) in self.channels.iter_mut().zip(self.channel_kinds.iter()) {
+            if *channel_kind == kind {
+                channel.add_sample(idx, &sample);
+            }
+        }
+    }
+
+    /// Add `sample` to every pixel within `filter`'s radius of where it was taken, weighted
+    /// by the filter. Ids only go to their own pixel, since they can't be blended with a
+    /// neighbor's. Cryptomatte coverage is weighted by the magnitude of the filter, as
+    /// coverage can't be negative and is divided by its own total weight.
+    fn splat_sample<F: Filter>(&mut self, coord: SampleCoord, sample: ChannelSample, filter: &F) {
+        let absolute_weights = match sample {
+            ChannelSample::MaterialId(..) | ChannelSample::ObjectId(..) => {
+                self.add_to_pixel(coord, sample);
+                return;
+            }
+            ChannelSample::CryptoObject(..) | ChannelSample::CryptoMaterial(..) => true,
+            _ => false,
+        };
+
+        let kind = sample.kind();
+        let channel = match self.channel_kinds.iter().position(|k| *k == kind) {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        let radius = self.padding as isize;
+        for dy in -radius..=radius {
+            let weight_y = filter.evaluate(dy as f32 - coord.offset.y);
+            if weight_y == 0.0 {
+                continue;
+            }
+            let y = (coord.pixel.y + self.padding) as isize + dy;
+            for dx in -radius..=radius {
+                let mut weight = filter.evaluate(dx as f32 - coord.offset.x) * weight_y;
+                if weight == 0.0 {
+                    continue;
+                }
+                if absolute_weights {
+                    weight = weight.abs();
+                }
+                let x = (coord.pixel.x + self.padding) as isize + dx;
+                let idx = x as usize + y as usize * self.storage_extent.w;
+                self.channels[channel].add_weighted_sample(idx, &sample, weight);
+            }
         }
     }
 }
 
-pub struct Film<N: ArrayLength<ChannelStorage>> {
+pub struct Film {
     channel_indices: HashMap<ChannelKind, usize>,
-    channels: Mutex<GenericArray<ChannelStorage, N>>,
+    // names of the objects or materials hashed into each cryptomatte channel
+    cryptomatte_names: HashMap<ChannelKind, Vec<String>>,
+    light_group_names: Vec<String>,
+    reconstruction: Reconstruction,
+    channels: Mutex<Vec<ChannelStorage>>,
+    // camera samples per pixel accumulated into the channels since they were last cleared,
+    // not counting adaptive samples
+    samples_taken: usize,
+    // camera samples accumulated into each pixel, including adaptive samples
+    pixel_samples: Mutex<Vec<usize>>,
+    // sums of the luminances and squared luminances of those samples, which adaptive
+    // sampling judges how noisy each pixel still is by
+    luminance_sums: Mutex<Vec<(f32, f32)>>,
+    // how far into the sample sequences previous passes have gone
+    sequence_offset: u64,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    sampler: AnySampler,
+    // per pixel scrambles of the sample sequences, instead of independent random ones
+    blue_noise: Option<BlueNoiseMask>,
+    // applied to the color output when saving, leaving the accumulated channel untouched
+    denoiser: Option<Denoiser>,
+    // standard deviations above its neighbors at which a pixel of the color output is
+    // considered a firefly and replaced
+    outlier_rejection: Option<f32>,
+    // near and far distances the depth output is normalized between, if it isn't left raw
+    depth_range: Option<Range<f32>>,
+    // applied in order to the linear color output when saving
+    post_effects: Vec<Box<dyn PostEffect>>,
+    // the frame last rendered or resumed, which post effects that change over time follow
+    frame: usize,
     progressive_epoch: usize,
     this_epoch_tiles_finished: AtomicUsize,
     res: Extent2u,
 }
 
-impl<'a, N: ArrayLength<ChannelStorage>> Film<N> {
+impl Film {
     pub fn new(channels: &[ChannelKind], res: Extent2u) -> Result<Self, String> {
         let mut channel_indices = HashMap::new();
         for (i, kind) in channels.iter().enumerate() {
@@ -189,18 +687,270 @@ impl<'a, N: ArrayLength<ChannelStorage>> Film<N> {
         }
         Ok(Film {
             channel_indices,
+            cryptomatte_names: HashMap::new(),
+            light_group_names: Vec::new(),
+            reconstruction: Reconstruction::ImportanceSampled,
             channels: Mutex::new(
-                GenericArray::from_exact_iter(
-                    channels.iter().map(|kind| ChannelStorage::new(*kind, res)),
-                )
-                .expect("Generic type length does not match the number of channels."),
+                channels
+                    .iter()
+                    .map(|kind| ChannelStorage::new(*kind, res))
+                    .collect(),
             ),
+            samples_taken: 0,
+            pixel_samples: Mutex::new(vec![0; res.w * res.h]),
+            luminance_sums: Mutex::new(vec![(0.0, 0.0); res.w * res.h]),
+            sequence_offset: 0,
+            adaptive_sampling: None,
+            sampler: AnySampler::default(),
+            blue_noise: None,
+            denoiser: None,
+            outlier_rejection: None,
+            depth_range: None,
+            post_effects: Vec::new(),
+            frame: 0,
             progressive_epoch: 0,
             this_epoch_tiles_finished: AtomicUsize::new(0),
             res,
         })
     }
 
+    /// Number of camera samples per pixel rendered since the film was created or cleared.
+    pub fn samples_taken(&self) -> usize {
+        self.samples_taken
+    }
+
+    /// Clear every channel, so that the next call to `render_frame_into` starts a new image
+    /// rather than adding more samples to the current one.
+    pub fn clear(&mut self) {
+        for channel in self.channels.get_mut().unwrap().iter_mut() {
+            channel.clear();
+        }
+        self.samples_taken = 0;
+        for count in self.pixel_samples.get_mut().unwrap().iter_mut() {
+            *count = 0;
+        }
+        for sums in self.luminance_sums.get_mut().unwrap().iter_mut() {
+            *sums = (0.0, 0.0);
+        }
+        self.sequence_offset = 0;
+    }
+
+    pub fn set_adaptive_sampling(&mut self, adaptive_sampling: Option<AdaptiveSampling>) {
+        self.adaptive_sampling = adaptive_sampling;
+    }
+
+    /// Choose the sequence that sample positions are drawn from. Changing it partway through
+    /// an image breaks the progression of the sequences, so clear the film first.
+    pub fn set_sampler(&mut self, sampler: AnySampler) {
+        self.sampler = sampler;
+    }
+
+    /// Take each pixel's scramble from a tiled blue noise mask, spreading error out evenly
+    /// over the image, instead of from white noise. Only samplers randomized by rotation keep
+    /// the mask's blue noise; the Owen and Halton scrambled ones would just use it as a seed.
+    pub fn set_blue_noise(&mut self, mask: Option<BlueNoiseMask>) {
+        self.blue_noise = mask;
+    }
+
+    /// Denoise the color output when saving, guided by whichever of the `WorldNormal`,
+    /// `Albedo` and `Depth` channels the film has.
+    pub fn set_denoiser(&mut self, denoiser: Option<Denoiser>) {
+        self.denoiser = denoiser;
+    }
+
+    /// Replace isolated pixels of the color output that are more than `sigmas` standard
+    /// deviations brighter than their neighbors when saving, before any denoising.
+    pub fn set_outlier_rejection(&mut self, sigmas: Option<f32>) {
+        self.outlier_rejection = sigmas;
+    }
+
+    /// Write the `Z` channel of the depth output as `(depth - near) / (far - near)`, clamped
+    /// to `[0, 1]`, rather than as the raw distance from the camera. Pixels with no depth are
+    /// left at zero either way.
+    pub fn set_depth_range(&mut self, range: Option<Range<f32>>) {
+        self.depth_range = range;
+    }
+
+    /// Add an effect to the end of the stack applied to the color output when saving,
+    /// after denoising and before tone mapping.
+    pub fn add_post_effect<E: PostEffect + 'static>(&mut self, effect: E) {
+        self.post_effects.push(Box::new(effect));
+    }
+
+    /// Splatted reconstruction needs a `FilterWeight` channel to keep the weight sums in.
+    pub fn set_reconstruction(&mut self, reconstruction: Reconstruction) -> Result<(), String> {
+        if reconstruction == Reconstruction::Splatted
+            && !self
+                .channel_indices
+                .contains_key(&ChannelKind::FilterWeight)
+        {
+            return Err(String::from(
+                "Splatted reconstruction requires a FilterWeight channel",
+            ));
+        }
+        self.reconstruction = reconstruction;
+        Ok(())
+    }
+
+    /// Set the names used for the output files of each `LightGroup` channel.
+    pub fn set_light_group_names(&mut self, names: &[String]) {
+        self.light_group_names = names.to_vec();
+    }
+
+    /// Set the names that may appear in a cryptomatte channel, written to its manifest.
+    pub fn set_cryptomatte_names(&mut self, kind: ChannelKind, names: &[String]) {
+        self.cryptomatte_names.insert(kind, names.to_vec());
+    }
+
+    /// Save the accumulated channels, sample counts and render settings so that rendering
+    /// `frame` can later be resumed with `load_checkpoint`.
+    pub fn save_checkpoint<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        frame: usize,
+    ) -> Result<(), String> {
+        let mut w = CheckpointWriter::new();
+
+        w.u64(self.res.w as u64);
+        w.u64(self.res.h as u64);
+        w.u64(frame as u64);
+
+        w.u8(match self.reconstruction {
+            Reconstruction::ImportanceSampled => 0,
+            Reconstruction::Splatted => 1,
+        });
+        match self.adaptive_sampling {
+            Some(adaptive) => {
+                w.u8(1);
+                w.f32(adaptive.threshold);
+                w.u64(adaptive.max_rounds as u64);
+            }
+            None => w.u8(0),
+        }
+        w.string(self.sampler.name());
+        w.u8(self.blue_noise.is_some() as u8);
+
+        w.u64(self.samples_taken as u64);
+        w.u64(self.sequence_offset);
+        w.u64(self.progressive_epoch as u64);
+        for count in self.pixel_samples.lock().unwrap().iter() {
+            w.u64(*count as u64);
+        }
+        for (sum, sq_sum) in self.luminance_sums.lock().unwrap().iter() {
+            w.f32(*sum);
+            w.f32(*sq_sum);
+        }
+
+        let channels = self.channels.lock().unwrap();
+        w.u64(channels.len() as u64);
+        for channel in channels.iter() {
+            w.string(&format!("{:?}", channel.kind()));
+            channel.write_checkpoint(&mut w);
+        }
+
+        w.save(path)
+    }
+
+    /// Restore a film saved with `save_checkpoint`, returning the frame it was rendering.
+    /// The next call to `render_frame_into` continues the sample sequences where the saved
+    /// render left off. The film must have the same resolution, channels, reconstruction,
+    /// adaptive sampling, sampler and pixel scrambling, and is left untouched if the checkpoint can't be loaded.
+    pub fn load_checkpoint<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<usize, String> {
+        let mut r = CheckpointReader::open(path)?;
+
+        let res = Extent2u::new(r.u64()? as usize, r.u64()? as usize);
+        if res != self.res {
+            return Err(format!(
+                "Checkpoint resolution {}x{} does not match the film's {}x{}",
+                res.w, res.h, self.res.w, self.res.h
+            ));
+        }
+        let frame = r.u64()? as usize;
+
+        let reconstruction = match r.u8()? {
+            0 => Reconstruction::ImportanceSampled,
+            1 => Reconstruction::Splatted,
+            other => return Err(format!("Unknown reconstruction in checkpoint: {}", other)),
+        };
+        if reconstruction != self.reconstruction {
+            return Err(format!(
+                "Checkpoint was rendered with {:?} reconstruction, not {:?}",
+                reconstruction, self.reconstruction
+            ));
+        }
+        let adaptive_sampling = match r.u8()? {
+            0 => None,
+            _ => Some(AdaptiveSampling {
+                threshold: r.f32()?,
+                max_rounds: r.u64()? as usize,
+            }),
+        };
+        if adaptive_sampling != self.adaptive_sampling {
+            return Err(format!(
+                "Checkpoint was rendered with adaptive sampling {:?}, not {:?}",
+                adaptive_sampling, self.adaptive_sampling
+            ));
+        }
+        let sampler = AnySampler::from_name(&r.string()?)?;
+        if sampler != self.sampler {
+            return Err(format!(
+                "Checkpoint was rendered with the {} sampler, not {}",
+                sampler.name(),
+                self.sampler.name()
+            ));
+        }
+        if (r.u8()? != 0) != self.blue_noise.is_some() {
+            return Err(String::from(
+                "Checkpoint was rendered with different pixel scrambling",
+            ));
+        }
+
+        let samples_taken = r.u64()? as usize;
+        let sequence_offset = r.u64()?;
+        let progressive_epoch = r.u64()? as usize;
+        let mut pixel_samples = vec![0; self.res.w * self.res.h];
+        for count in pixel_samples.iter_mut() {
+            *count = r.u64()? as usize;
+        }
+        let mut luminance_sums = vec![(0.0, 0.0); self.res.w * self.res.h];
+        for sums in luminance_sums.iter_mut() {
+            *sums = (r.f32()?, r.f32()?);
+        }
+
+        let channels = self.channels.get_mut().unwrap();
+        if r.u64()? as usize != channels.len() {
+            return Err(String::from(
+                "Checkpoint does not have the same channels as the film",
+            ));
+        }
+        let mut loaded = Vec::with_capacity(channels.len());
+        for channel in channels.iter() {
+            let kind = r.string()?;
+            if kind != format!("{:?}", channel.kind()) {
+                return Err(format!(
+                    "Checkpoint has a {} channel where the film has {:?}",
+                    kind,
+                    channel.kind()
+                ));
+            }
+            let mut storage = ChannelStorage::new(channel.kind(), self.res);
+            storage.read_checkpoint(&mut r)?;
+            loaded.push(storage);
+        }
+
+        for (channel, storage) in channels.iter_mut().zip(loaded) {
+            *channel = storage;
+        }
+        self.samples_taken = samples_taken;
+        self.sequence_offset = sequence_offset;
+        self.progressive_epoch = progressive_epoch;
+        *self.pixel_samples.get_mut().unwrap() = pixel_samples;
+        *self.luminance_sums.get_mut().unwrap() = luminance_sums;
+        self.frame = frame;
+
+        Ok(frame)
+    }
+
     pub fn save_to<P: AsRef<std::path::Path>, IS: Into<String>>(
         &self,
         write_channels: &[ChannelKind],
@@ -218,6 +968,38 @@ impl<'a, N: ArrayLength<ChannelStorage>> Film<N> {
 
         let channels = self.channels.lock().unwrap();
 
+        let mut processed_color = None;
+        if let (Some(&color_idx), true) = (
+            self.channel_indices.get(&ChannelKind::Color),
+            write_channels.contains(&ChannelKind::Color),
+        ) {
+            let color_buf = channel_storage_index!(channels, Color, color_idx);
+
+            if let Some(sigmas) = self.outlier_rejection {
+                processed_color = Some(denoise::reject_outliers(color_buf, self.res, sigmas));
+            }
+
+            if let Some(denoiser) = self.denoiser {
+                println!("Denoising...");
+                let features = Features {
+                    normal: self
+                        .channel_indices
+                        .get(&ChannelKind::WorldNormal)
+                        .map(|&idx| &channel_storage_index!(channels, WorldNormal, idx)[..]),
+                    albedo: self
+                        .channel_indices
+                        .get(&ChannelKind::Albedo)
+                        .map(|&idx| &channel_storage_index!(channels, Albedo, idx)[..]),
+                    depth: self
+                        .channel_indices
+                        .get(&ChannelKind::Depth)
+                        .map(|&idx| &channel_storage_index!(channels, Depth, idx)[..]),
+                };
+                let input = processed_color.as_ref().unwrap_or(color_buf);
+                processed_color = Some(denoiser.denoise(input, &features, self.res));
+            }
+        }
+
         for kind in write_channels.iter() {
             match *kind {
                 ChannelKind::Color => {
@@ -225,17 +1007,58 @@ impl<'a, N: ArrayLength<ChannelStorage>> Film<N> {
                     let alpha_idx = self.channel_indices.get(&ChannelKind::Alpha);
                     let bg_idx = self.channel_indices.get(&ChannelKind::Background);
 
-                    match (color_idx, alpha_idx, bg_idx, transparent_background) {
-                        (Some(&color_idx), Some(&alpha_idx), _, true) => {
-                            let color_buf = &channel_storage_index!(channels, Color, color_idx);
-                            let alpha_buf = &channel_storage_index!(channels, Alpha, alpha_idx);
+                    let color_buf = match (&processed_color, color_idx) {
+                        (Some(buf), _) => buf,
+                        (None, Some(&color_idx)) => {
+                            channel_storage_index!(channels, Color, color_idx)
+                        }
+                        (None, None) => {
+                            return Err(String::from(
+                                "Attempted to write Color channel but it didn't exist",
+                            ))
+                        }
+                    };
+
+                    // composite before post processing, so that bright lights seen directly
+                    // in the background glow too
+                    let (mut hdr, alpha_buf) = match (alpha_idx, bg_idx, transparent_background) {
+                        (Some(&alpha_idx), _, true) => (
+                            color_buf.clone(),
+                            Some(channel_storage_index!(channels, Alpha, alpha_idx)),
+                        ),
+                        (_, Some(&bg_idx), false) => {
+                            let bg_buf = channel_storage_index!(channels, Background, bg_idx);
+                            let composited = color_buf
+                                .iter()
+                                .zip(bg_buf.iter())
+                                .map(|(col, bg)| *col + *bg)
+                                .collect::<Vec<_>>();
+                            (composited, None)
+                        }
+                        (_, None, false) => (color_buf.clone(), None),
+                        _ => {
+                            return Err(String::from(
+                                "Attempted to write Color channel with insufficient channels",
+                            ))
+                        }
+                    };
+
+                    for effect in self.post_effects.iter() {
+                        effect.apply(&mut hdr, self.res, self.frame);
+                    }
+
+                    let filename = output_folder
+                        .as_ref()
+                        .join(format!("{}_color.png", base_name.clone()));
+                    println!("Saving to {}...", filename.display());
+                    match alpha_buf {
+                        Some(alpha_buf) => {
                             let mut img =
                                 image::RgbaImage::new(self.res.w as u32, self.res.h as u32);
                             for (x, y, pixel) in img.enumerate_pixels_mut() {
                                 let idx = x as usize + (self.res.h - 1 - y as usize) * self.res.w;
-                                let col = color_buf[idx];
                                 let a = alpha_buf[idx];
-                                let rgb = (col).saturated().gamma_corrected(2.2);
+                                let rgb = hdr[idx].saturated().gamma_corrected(2.2);
                                 *pixel = image::Rgba([
                                     (rgb.x * 255.0).min(255.0).max(0.0) as u8,
                                     (rgb.y * 255.0).min(255.0).max(0.0) as u8,
@@ -243,58 +1066,22 @@ impl<'a, N: ArrayLength<ChannelStorage>> Film<N> {
                                     (a * 255.0).min(255.0).max(0.0) as u8,
                                 ]);
                             }
-                            let filename = output_folder
-                                .as_ref()
-                                .join(format!("{}_color.png", base_name.clone()));
-                            println!("Saving to {}...", filename.display());
-                            img.save(filename).unwrap();
-                        }
-                        (Some(&color_idx), _, Some(&bg_idx), false) => {
-                            let color_buf = channel_storage_index!(channels, Color, color_idx);
-                            let bg_buf = channel_storage_index!(channels, Background, bg_idx);
-                            let mut img =
-                                image::RgbImage::new(self.res.w as u32, self.res.h as u32);
-                            for (x, y, pixel) in img.enumerate_pixels_mut() {
-                                let i = x as usize + (self.res.h - 1 - y as usize) * self.res.w;
-                                let col = color_buf[i];
-                                let bg = bg_buf[i];
-                                let rgb = (col + bg).saturated().gamma_corrected(2.2);
-                                *pixel = image::Rgb([
-                                    (rgb.x * 255.0).min(255.0).max(0.0) as u8,
-                                    (rgb.y * 255.0).min(255.0).max(0.0) as u8,
-                                    (rgb.z * 255.0).min(255.0).max(0.0) as u8,
-                                ]);
-                            }
-                            let filename = output_folder
-                                .as_ref()
-                                .join(format!("{}_color.png", base_name.clone()));
-                            println!("Saving to {}...", filename.display());
                             img.save(filename).unwrap();
                         }
-                        (Some(&color_idx), _, None, false) => {
-                            let color_buf = channel_storage_index!(channels, Color, color_idx);
+                        None => {
                             let mut img =
                                 image::RgbImage::new(self.res.w as u32, self.res.h as u32);
                             for (x, y, pixel) in img.enumerate_pixels_mut() {
                                 let idx = x as usize + (self.res.h - 1 - y as usize) * self.res.w;
-                                let rgb = color_buf[idx].gamma_corrected(2.2);
+                                let rgb = hdr[idx].saturated().gamma_corrected(2.2);
                                 *pixel = image::Rgb([
                                     (rgb.x * 255.0).min(255.0).max(0.0) as u8,
                                     (rgb.y * 255.0).min(255.0).max(0.0) as u8,
                                     (rgb.z * 255.0).min(255.0).max(0.0) as u8,
                                 ]);
                             }
-                            let filename = output_folder
-                                .as_ref()
-                                .join(format!("{}_color.png", base_name.clone()));
-                            println!("Saving to {}...", filename.display());
                             img.save(filename).unwrap();
                         }
-                        _ => {
-                            return Err(String::from(
-                                "Attempted to write Color channel with insufficient channels",
-                            ))
-                        }
                     }
                 }
                 ChannelKind::Background => {
@@ -370,35 +1157,455 @@ impl<'a, N: ArrayLength<ChannelStorage>> Film<N> {
                     println!("Saving to {}...", filename.display());
                     img.save(filename).unwrap();
                 }
+                ChannelKind::Depth => {
+                    let idx = *self
+                        .channel_indices
+                        .get(&ChannelKind::Depth)
+                        .ok_or_else(|| {
+                            String::from("Attempted to write Depth channel but it didn't exist")
+                        })?;
+                    let buf = channel_storage_index!(channels, Depth, idx);
+                    let depths = self.top_down(buf);
+
+                    // rays that escape the scene hit the sky sphere, so their depth is about
+                    // the world's radius rather than infinite
+                    let z: Vec<f32> = match &self.depth_range {
+                        Some(range) => depths
+                            .iter()
+                            .map(|d| {
+                                if *d > 0.0 {
+                                    ((d - range.start) / (range.end - range.start))
+                                        .min(1.0)
+                                        .max(0.0)
+                                } else {
+                                    0.0
+                                }
+                            })
+                            .collect(),
+                        None => depths.clone(),
+                    };
+                    let mut exr = ExrImage::new(self.res.w, self.res.h);
+                    exr.add_channel("Z", z);
+                    let filename = output_folder
+                        .as_ref()
+                        .join(format!("{}_depth.exr", base_name.clone()));
+                    println!("Saving to {}...", filename.display());
+                    exr.save(filename)?;
+
+                    // preview normalized between the nearest and furthest depths, where
+                    // pixels with no depth are left black
+                    let (min, max) = depths
+                        .iter()
+                        .filter(|d| **d > 0.0)
+                        .fold((std::f32::MAX, 0f32), |(min, max), d| {
+                            (min.min(*d), max.max(*d))
+                        });
+                    let range = (max - min).max(std::f32::EPSILON);
+                    let mut img = image::GrayImage::new(self.res.w as u32, self.res.h as u32);
+                    for (pixel, d) in img.pixels_mut().zip(depths.iter()) {
+                        let v = if *d > 0.0 {
+                            1.0 - (d - min) / range
+                        } else {
+                            0.0
+                        };
+                        *pixel = image::Luma([(v * 255.0).min(255.0).max(0.0) as u8]);
+                    }
+                    let filename = output_folder
+                        .as_ref()
+                        .join(format!("{}_depth.png", base_name.clone()));
+                    println!("Saving to {}...", filename.display());
+                    img.save(filename).unwrap();
+                }
+                ChannelKind::Albedo => {
+                    let idx = *self
+                        .channel_indices
+                        .get(&ChannelKind::Albedo)
+                        .ok_or_else(|| {
+                            String::from("Attempted to write Albedo channel but it didn't exist")
+                        })?;
+                    let buf = channel_storage_index!(channels, Albedo, idx);
+                    self.save_srgb(
+                        buf,
+                        output_folder.as_ref(),
+                        &format!("{}_albedo", base_name),
+                    )?;
+                }
+                ChannelKind::LightGroup(group) => {
+                    let idx = *self.channel_indices.get(kind).ok_or_else(|| {
+                        format!("Attempted to write {:?} channel but it didn't exist", kind)
+                    })?;
+                    let buf = channel_storage_index!(channels, LightGroup, idx);
+                    let name = match self.light_group_names.get(group) {
+                        Some(name) => name.clone(),
+                        None => group.to_string(),
+                    };
+                    self.save_srgb(
+                        buf,
+                        output_folder.as_ref(),
+                        &format!("{}_light_{}", base_name, name),
+                    )?;
+                }
+                ChannelKind::Direct
+                | ChannelKind::Indirect
+                | ChannelKind::Diffuse
+                | ChannelKind::Specular => {
+                    let idx = *self.channel_indices.get(kind).ok_or_else(|| {
+                        format!("Attempted to write {:?} channel but it didn't exist", kind)
+                    })?;
+                    let (buf, name) = match *kind {
+                        ChannelKind::Direct => {
+                            (channel_storage_index!(channels, Direct, idx), "direct")
+                        }
+                        ChannelKind::Indirect => {
+                            (channel_storage_index!(channels, Indirect, idx), "indirect")
+                        }
+                        ChannelKind::Diffuse => {
+                            (channel_storage_index!(channels, Diffuse, idx), "diffuse")
+                        }
+                        _ => (channel_storage_index!(channels, Specular, idx), "specular"),
+                    };
+                    self.save_srgb(
+                        buf,
+                        output_folder.as_ref(),
+                        &format!("{}_{}", base_name, name),
+                    )?;
+                }
+                ChannelKind::MaterialId | ChannelKind::ObjectId => {
+                    let (buf, name) = match *kind {
+                        ChannelKind::MaterialId => {
+                            let idx = *self
+                                .channel_indices
+                                .get(&ChannelKind::MaterialId)
+                                .ok_or_else(|| {
+                                    String::from(
+                                        "Attempted to write MaterialId channel but it didn't exist",
+                                    )
+                                })?;
+                            (
+                                channel_storage_index!(channels, MaterialId, idx),
+                                "material_id",
+                            )
+                        }
+                        _ => {
+                            let idx = *self
+                                .channel_indices
+                                .get(&ChannelKind::ObjectId)
+                                .ok_or_else(|| {
+                                    String::from(
+                                        "Attempted to write ObjectId channel but it didn't exist",
+                                    )
+                                })?;
+                            (channel_storage_index!(channels, ObjectId, idx), "object_id")
+                        }
+                    };
+                    let ids = self.top_down(buf);
+
+                    let mut exr = ExrImage::new(self.res.w, self.res.h);
+                    exr.add_channel("id", ids.iter().map(|id| id.as_f32()).collect());
+                    let filename =
+                        output_folder
+                            .as_ref()
+                            .join(format!("{}_{}.exr", base_name.clone(), name));
+                    println!("Saving to {}...", filename.display());
+                    exr.save(filename)?;
+
+                    let mut img = image::RgbImage::new(self.res.w as u32, self.res.h as u32);
+                    for (pixel, id) in img.pixels_mut().zip(ids.iter()) {
+                        let rgb = id.false_color();
+                        *pixel = image::Rgb([
+                            (rgb.x * 255.0).min(255.0).max(0.0) as u8,
+                            (rgb.y * 255.0).min(255.0).max(0.0) as u8,
+                            (rgb.z * 255.0).min(255.0).max(0.0) as u8,
+                        ]);
+                    }
+                    let filename =
+                        output_folder
+                            .as_ref()
+                            .join(format!("{}_{}.png", base_name.clone(), name));
+                    println!("Saving to {}...", filename.display());
+                    img.save(filename).unwrap();
+                }
+                ChannelKind::CryptoObject | ChannelKind::CryptoMaterial => {
+                    let idx = *self.channel_indices.get(kind).ok_or_else(|| {
+                        format!("Attempted to write {:?} channel but it didn't exist", kind)
+                    })?;
+                    let (buf, layer, name) = match *kind {
+                        ChannelKind::CryptoObject => (
+                            channel_storage_index!(channels, CryptoObject, idx),
+                            "CryptoObject",
+                            "crypto_object",
+                        ),
+                        _ => (
+                            channel_storage_index!(channels, CryptoMaterial, idx),
+                            "CryptoMaterial",
+                            "crypto_material",
+                        ),
+                    };
+                    let names = self
+                        .cryptomatte_names
+                        .get(kind)
+                        .map(|names| names.as_slice())
+                        .unwrap_or(&[]);
+
+                    let mut exr = ExrImage::new(self.res.w, self.res.h);
+                    cryptomatte::add_layers(&mut exr, layer, &self.top_down(buf), names);
+                    let filename =
+                        output_folder
+                            .as_ref()
+                            .join(format!("{}_{}.exr", base_name.clone(), name));
+                    println!("Saving to {}...", filename.display());
+                    exr.save(filename)?;
+                }
+                ChannelKind::WorldPosition => {
+                    let idx = *self
+                        .channel_indices
+                        .get(&ChannelKind::WorldPosition)
+                        .ok_or_else(|| {
+                            String::from(
+                                "Attempted to write WorldPosition channel but it didn't exist",
+                            )
+                        })?;
+                    let buf = channel_storage_index!(channels, WorldPosition, idx);
+                    let positions = self.top_down(buf);
+
+                    let mut exr = ExrImage::new(self.res.w, self.res.h);
+                    exr.add_channel("X", positions.iter().map(|p| p.x).collect());
+                    exr.add_channel("Y", positions.iter().map(|p| p.y).collect());
+                    exr.add_channel("Z", positions.iter().map(|p| p.z).collect());
+                    let filename = output_folder
+                        .as_ref()
+                        .join(format!("{}_position.exr", base_name.clone()));
+                    println!("Saving to {}...", filename.display());
+                    exr.save(filename)?;
+                }
+                ChannelKind::SampleCount => {
+                    let idx = *self
+                        .channel_indices
+                        .get(&ChannelKind::SampleCount)
+                        .ok_or_else(|| {
+                            String::from(
+                                "Attempted to write SampleCount channel but it didn't exist",
+                            )
+                        })?;
+                    let buf = channel_storage_index!(channels, SampleCount, idx);
+                    let counts = self.top_down(buf);
+
+                    let mut exr = ExrImage::new(self.res.w, self.res.h);
+                    exr.add_channel("Y", counts.clone());
+                    let filename = output_folder
+                        .as_ref()
+                        .join(format!("{}_samples.exr", base_name.clone()));
+                    println!("Saving to {}...", filename.display());
+                    exr.save(filename)?;
+
+                    // heatmap from the fewest samples in black to the most in white
+                    let min = counts.iter().cloned().fold(std::f32::MAX, f32::min);
+                    let max = counts.iter().cloned().fold(0.0, f32::max);
+                    let range = (max - min).max(1.0);
+                    let mut img = image::GrayImage::new(self.res.w as u32, self.res.h as u32);
+                    for (pixel, count) in img.pixels_mut().zip(counts.iter()) {
+                        let v = (count - min) / range;
+                        *pixel = image::Luma([(v * 255.0).min(255.0).max(0.0) as u8]);
+                    }
+                    let filename = output_folder
+                        .as_ref()
+                        .join(format!("{}_samples.png", base_name.clone()));
+                    println!("Saving to {}...", filename.display());
+                    img.save(filename).unwrap();
+                }
+                ChannelKind::FilterWeight => {
+                    let idx = *self
+                        .channel_indices
+                        .get(&ChannelKind::FilterWeight)
+                        .ok_or_else(|| {
+                            String::from(
+                                "Attempted to write FilterWeight channel but it didn't exist",
+                            )
+                        })?;
+                    let buf = channel_storage_index!(channels, FilterWeight, idx);
+
+                    let mut exr = ExrImage::new(self.res.w, self.res.h);
+                    exr.add_channel("Y", self.top_down(buf));
+                    let filename = output_folder
+                        .as_ref()
+                        .join(format!("{}_weight.exr", base_name.clone()));
+                    println!("Saving to {}...", filename.display());
+                    exr.save(filename)?;
+                }
+                ChannelKind::MotionVector => {
+                    let idx = *self
+                        .channel_indices
+                        .get(&ChannelKind::MotionVector)
+                        .ok_or_else(|| {
+                            String::from(
+                                "Attempted to write MotionVector channel but it didn't exist",
+                            )
+                        })?;
+                    let buf = channel_storage_index!(channels, MotionVector, idx);
+                    let motions = self
+                        .top_down(buf)
+                        .into_iter()
+                        .map(|m| m.pixels())
+                        .collect::<Vec<_>>();
+
+                    // forward motion in pixels, x to the right and y up
+                    let mut exr = ExrImage::new(self.res.w, self.res.h);
+                    exr.add_channel("R", motions.iter().map(|m| m.x).collect());
+                    exr.add_channel("G", motions.iter().map(|m| m.y).collect());
+                    let filename = output_folder
+                        .as_ref()
+                        .join(format!("{}_motion.exr", base_name.clone()));
+                    println!("Saving to {}...", filename.display());
+                    exr.save(filename)?;
+                }
             }
         }
         Ok(())
     }
+
+    /// Save a color buffer as a linear EXR and a gamma corrected PNG preview.
+    fn save_srgb(
+        &self,
+        buf: &[Srgb],
+        output_folder: &std::path::Path,
+        file_stem: &str,
+    ) -> Result<(), String> {
+        let colors = self.top_down(buf);
+
+        let mut exr = ExrImage::new(self.res.w, self.res.h);
+        exr.add_channel("R", colors.iter().map(|c| c.x).collect());
+        exr.add_channel("G", colors.iter().map(|c| c.y).collect());
+        exr.add_channel("B", colors.iter().map(|c| c.z).collect());
+        let filename = output_folder.join(format!("{}.exr", file_stem));
+        println!("Saving to {}...", filename.display());
+        exr.save(filename)?;
+
+        let mut img = image::RgbImage::new(self.res.w as u32, self.res.h as u32);
+        for (pixel, color) in img.pixels_mut().zip(colors.iter()) {
+            let rgb = color.saturated().gamma_corrected(2.2);
+            *pixel = image::Rgb([
+                (rgb.x * 255.0).min(255.0).max(0.0) as u8,
+                (rgb.y * 255.0).min(255.0).max(0.0) as u8,
+                (rgb.z * 255.0).min(255.0).max(0.0) as u8,
+            ]);
+        }
+        let filename = output_folder.join(format!("{}.png", file_stem));
+        println!("Saving to {}...", filename.display());
+        img.save(filename).unwrap();
+        Ok(())
+    }
+
+    /// Copy a channel buffer into rows ordered from the top of the image down, as images are
+    /// written.
+    fn top_down<T: Copy>(&self, buf: &[T]) -> Vec<T> {
+        (0..self.res.h)
+            .rev()
+            .flat_map(|y| buf[y * self.res.w..(y + 1) * self.res.w].iter().copied())
+            .collect()
+    }
 }
 
-impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<N> {
+impl Film {
+    /// Render `samples` wide samples in every pixel of `frame`, adding them to the image.
     #[allow(clippy::too_many_arguments)]
     pub fn render_frame_into<I, F>(
-        &'a mut self,
+        &mut self,
+        world: &World,
+        camera: CameraHandle,
+        integrator: &I,
+        filter: &F,
+        tile_size: Extent2u,
+        frame: usize,
+        shutter: &Shutter,
+        samples: usize,
+    ) where
+        F: Filter + Copy + Send + Sync,
+        I: Integrator,
+    {
+        self.render_samples_into(
+            world, camera, integrator, filter, tile_size, frame, shutter, samples, None,
+        );
+        self.samples_taken += samples * 4;
+    }
+
+    /// Take the rounds of adaptive sampling, if it's on, each adding `samples` more wide
+    /// samples to the pixels that are still noisy. Run once after the samples of a pass,
+    /// however many calls to `render_frame_into` those were split into, so that the image
+    /// doesn't depend on how the pass was split.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_adaptive_rounds_into<I, F>(
+        &mut self,
+        world: &World,
+        camera: CameraHandle,
+        integrator: &I,
+        filter: &F,
+        tile_size: Extent2u,
+        frame: usize,
+        shutter: &Shutter,
+        samples: usize,
+    ) where
+        F: Filter + Copy + Send + Sync,
+        I: Integrator,
+    {
+        let adaptive = match self.adaptive_sampling {
+            Some(adaptive) => adaptive,
+            None => return,
+        };
+        for _ in 0..adaptive.max_rounds {
+            self.render_samples_into(
+                world,
+                camera,
+                integrator,
+                filter,
+                tile_size,
+                frame,
+                shutter,
+                samples,
+                Some(adaptive.threshold),
+            );
+        }
+    }
+
+    /// Render `samples` wide samples into each pixel, or only into those noisier than
+    /// `threshold` if there is one.
+    #[allow(clippy::too_many_arguments)]
+    fn render_samples_into<I, F>(
+        &mut self,
         world: &World,
         camera: CameraHandle,
         integrator: &I,
         filter: &F,
         tile_size: Extent2u,
         frame: usize,
-        time_range: Range<f32>,
+        shutter: &Shutter,
         samples: usize,
+        threshold: Option<f32>,
     ) where
-        F: Filter + Copy + Send,
+        F: Filter + Copy + Send + Sync,
         I: Integrator,
     {
+        self.frame = frame;
         let camera = world.cameras.get(camera);
         let mut tiles = Vec::new();
 
+        let reconstruction = self.reconstruction;
+        let padding = match reconstruction {
+            Reconstruction::ImportanceSampled => 0,
+            Reconstruction::Splatted => filter.radius().ceil() as usize,
+        };
+
+        // splatted tiles are summed onto the weighted sums of previous passes
+        if reconstruction == Reconstruction::Splatted && self.samples_taken > 0 {
+            self.denormalize_splats();
+        }
+
         let rem = Vec2u::new((self.res.w) % tile_size.w, (self.res.h) % tile_size.h);
         {
             let mut idx = 0;
             let channels = self.channels.lock().unwrap();
+            let pixel_samples = self.pixel_samples.lock().unwrap();
+            let luminance_sums = self.luminance_sums.lock().unwrap();
+
             for tile_x in 0..((self.res.w + rem.x) / tile_size.w) {
                 for tile_y in 0..((self.res.h + rem.y) / tile_size.h) {
                     let start = Vec2u::new(tile_x * tile_size.w, tile_y * tile_size.h);
@@ -417,6 +1624,10 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
                         channels.iter().map(|c| c.kind()),
                         self.res,
                         tile_bounds,
+                        reconstruction,
+                        padding,
+                        &pixel_samples,
+                        &luminance_sums,
                     );
                     tiles.push(tile);
 
@@ -425,17 +1636,31 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
             }
         }
 
-        let fis = FilterImportanceSampler::new(filter);
+        let fis = match reconstruction {
+            Reconstruction::ImportanceSampled => Some(FilterImportanceSampler::new(filter)),
+            Reconstruction::Splatted => None,
+        };
 
         let sets_1d = 1 + integrator.requested_1d_sample_sets();
         let sets_2d = 2 + integrator.requested_2d_sample_sets();
 
-        let sample_sets = Samples::new_rd(4 * samples, sets_1d, sets_2d, frame as u64);
-        // let sample_sets = Samples::new_random(4 * samples, sets_1d, sets_2d);
+        let sequence_len = 4 * samples;
+        let sample_sets = Samples::new(
+            &self.sampler,
+            sequence_len,
+            sets_1d,
+            sets_2d,
+            frame as u64,
+            self.sequence_offset,
+        );
+        // let sample_sets = Samples::new(&Random, 4 * samples, sets_1d, sets_2d, 0, 0);
 
         let width = self.res.w;
+        let height = self.res.h;
+        let blue_noise = self.blue_noise.clone();
+        let blue_noise = blue_noise.as_ref();
 
-        self.integrate_tiles(tiles, samples * 4, |tile| {
+        self.integrate_tiles(tiles, |tile| {
             // let mut rng = SmallRng::from_rng(thread_rng()).unwrap();
             // let offset = (tile.index as u64) << 32;
 
@@ -450,24 +1675,37 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
             let mut hit_store = HitStore::from_hitable_store(&hit_bump, &world.hitables);
             let mut bsdf_bump = Bump::new();
 
-            let time_range_range = f32x4::from(time_range.end - time_range.start);
-
             for x in tile.raster_bounds.min.x..tile.raster_bounds.max.x {
                 for y in tile.raster_bounds.min.y..tile.raster_bounds.max.y {
                     let tile_coord = Vec2u::new(x, y) - tile.raster_bounds.min;
 
-                    let mut rng = SmallRng::seed_from_u64((x + y * width) as u64);
-                    let scramble = rng.gen();
+                    // adaptive rounds only add samples to pixels that are still noisy
+                    if let Some(threshold) = threshold {
+                        if !tile.needs_samples(tile_coord, threshold) {
+                            continue;
+                        }
+                    }
+
+                    let scramble = match blue_noise {
+                        Some(mask) => mask.scramble(x, y, frame),
+                        None => {
+                            let mut rng = SmallRng::seed_from_u64((x + y * width) as u64);
+                            rng.gen()
+                        }
+                    };
+
+                    // raster y goes up from the bottom of the image
+                    let scanline = (height as f32 - (y as f32 + 0.5)) / height as f32;
 
                     for samp in 0..samples {
                         let sample_nums = [4 * samp, 4 * samp + 1, 4 * samp + 2, 4 * samp + 3];
 
-                        let ndcs = Wec2::from([
+                        let uvs = [
                             sample_uv(
                                 x,
                                 y,
                                 tile.screen_to_ndc_size,
-                                &fis,
+                                fis.as_ref(),
                                 &[
                                     sample_sets.sample_2d(0, sample_nums[0], scramble, 0),
                                     sample_sets.sample_2d(1, sample_nums[0], scramble, 0),
@@ -477,7 +1715,7 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
                                 x,
                                 y,
                                 tile.screen_to_ndc_size,
-                                &fis,
+                                fis.as_ref(),
                                 &[
                                     sample_sets.sample_2d(0, sample_nums[1], scramble, 0),
                                     sample_sets.sample_2d(1, sample_nums[1], scramble, 0),
@@ -487,7 +1725,7 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
                                 x,
                                 y,
                                 tile.screen_to_ndc_size,
-                                &fis,
+                                fis.as_ref(),
                                 &[
                                     sample_sets.sample_2d(0, sample_nums[2], scramble, 0),
                                     sample_sets.sample_2d(1, sample_nums[2], scramble, 0),
@@ -497,20 +1735,22 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
                                 x,
                                 y,
                                 tile.screen_to_ndc_size,
-                                &fis,
+                                fis.as_ref(),
                                 &[
                                     sample_sets.sample_2d(0, sample_nums[3], scramble, 0),
                                     sample_sets.sample_2d(1, sample_nums[3], scramble, 0),
                                 ],
                             ),
-                        ]);
+                        ];
+                        let ndcs = Wec2::from([uvs[0].0, uvs[1].0, uvs[2].0, uvs[3].0]);
 
-                        let times = f32x4::from(time_range.start)
-                            + time_range_range
-                            // * f32x4::from(rng.gen::<[f32; 4]>());
-                            * sample_sets.wide_sample_1d(sample_nums[0], scramble, 0);
+                        let times = shutter.sample_time(
+                            // f32x4::from(rng.gen::<[f32; 4]>()),
+                            sample_sets.wide_sample_1d(sample_nums[0], scramble, 0),
+                            scanline,
+                        );
 
-                        let rays = camera.get_rays(
+                        let mut rays = camera.get_rays(
                             scramble,
                             sample_nums,
                             tile_coord,
@@ -522,6 +1762,11 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
                             ],
                         );
 
+                        for (coord, (_, offset)) in rays.tile_coord.iter_mut().zip(uvs.iter()) {
+                            coord.offset = *offset;
+                            tile.add_camera_sample(*coord, filter);
+                        }
+
                         spawned_wrays.push(rays);
                     }
                 }
@@ -543,18 +1788,35 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
                     Box::new(#[inline] |t| f32x4::from(0.0001 * 2.0 * depth as f32) * t)
                 };
 
+                // camera rays already start at the near clip distance
+                let t_max = if depth == 0 {
+                    f32x4::from((camera.far_clip() - camera.near_clip()).min(world.radius * 2.0))
+                } else {
+                    f32x4::from(world.radius * 2.0)
+                };
+
                 for wray in spawned_wrays.drain(..) {
-                    world.hitables.add_hits(
-                        wray,
-                        f32x4::from(crate::WORLD_RADIUS * 2.0),
-                        &mut hit_store,
-                        &half_pixel_size_at
-                    );
+                    world
+                        .hitables
+                        .add_hits(wray, t_max, &mut hit_store, &half_pixel_size_at);
                 }
 
                 hit_store.process_hits(&world.hitables, &mut wintersections, &half_pixel_size_at);
 
-                for (mat_id, wshading_point) in wintersections.drain(..) {
+                for (hitable_id, mat_id, wshading_point) in wintersections.drain(..) {
+                    if depth == 0 {
+                        push_motion_vectors(
+                            world,
+                            camera,
+                            shutter,
+                            hitable_id,
+                            &wshading_point,
+                            tile.raster_bounds.min,
+                            Extent2u::new(width, height),
+                            &mut new_samples,
+                        );
+                    }
+
                     let samples_1d = [
                         sample_sets.wide_sample_1d_array(
                             wshading_point.ray.sample,
@@ -648,9 +1910,11 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
                     ];
                     integrator.integrate(
                         world,
+                        camera,
                         &samples_1d,
                         &samples_2d,
                         depth,
+                        hitable_id,
                         mat_id,
                         wshading_point,
                         &bsdf_bump,
@@ -659,8 +1923,8 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
                     );
                 }
 
-                for (tile_coord, sample) in new_samples.drain(..) {
-                    tile.add_sample(tile_coord, sample);
+                for (coord, sample) in new_samples.drain(..) {
+                    tile.add_sample(coord, sample, filter);
                 }
 
                 while spawned_rays.len() % 4 != 0 {
@@ -683,11 +1947,17 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
                 spawned_rays.clear();
             }
         });
+
+        if reconstruction == Reconstruction::Splatted {
+            self.normalize_splats();
+        }
+        self.sequence_offset += sequence_len as u64;
+        self.write_sample_counts();
     }
 
-    fn integrate_tiles<FN>(&mut self, tiles: Vec<Tile<N>>, samples: usize, integrate_tile: FN)
+    fn integrate_tiles<FN>(&mut self, tiles: Vec<Tile>, integrate_tile: FN)
     where
-        FN: FnOnce(&mut Tile<N>) + Send + Sync + Copy,
+        FN: FnOnce(&mut Tile) + Send + Sync + Copy,
     {
         let num_tiles = tiles.len();
 
@@ -701,7 +1971,7 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
                     scope.spawn_fifo(move |_| {
                         integrate_tile(&mut tile);
 
-                        this.tile_finished(tile, samples, pb)
+                        this.tile_finished(tile, pb)
                     })
                 }
             });
@@ -715,12 +1985,7 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
         self.progressive_epoch += 1;
     }
 
-    fn tile_finished(
-        &self,
-        tile: Tile<N>,
-        samples: usize,
-        pb: Arc<Mutex<pbr::ProgressBar<std::io::Stdout>>>,
-    ) {
+    fn tile_finished(&self, tile: Tile, pb: Arc<Mutex<pbr::ProgressBar<std::io::Stdout>>>) {
         if self.progressive_epoch != tile.epoch {
             panic!(
                 "Epoch mismatch! Expected: {}, got: {}",
@@ -732,36 +1997,171 @@ impl<'a, N: ArrayLength<ChannelStorage> + ArrayLength<ChannelTileStorage>> Film<
         pb.inc();
 
         let mut channels = self.channels.lock().unwrap();
+        let mut pixel_samples = self.pixel_samples.lock().unwrap();
+        let mut film_luminance_sums = self.luminance_sums.lock().unwrap();
 
         let Tile {
             channels: tile_channels,
             raster_bounds: tile_bounds,
+            padding,
+            sample_counts,
+            luminance_sums,
             ..
         } = tile;
 
         for (tile_channel, channel) in tile_channels.iter().zip(channels.iter_mut()) {
-            // Safe because we guarantee that we won't start modifying this chunk again
-            // until the next epoch.
-            channel
-                .copy_from_tile(tile_channel, self.res, tile_bounds, samples)
-                .unwrap();
+            match self.reconstruction {
+                // Safe because we guarantee that we won't start modifying this chunk again
+                // until the next epoch.
+                Reconstruction::ImportanceSampled => channel
+                    .accumulate_from_tile(
+                        tile_channel,
+                        self.res,
+                        tile_bounds,
+                        &pixel_samples,
+                        &sample_counts,
+                    )
+                    .unwrap(),
+                Reconstruction::Splatted => channel
+                    .add_from_tile(tile_channel, self.res, tile_bounds, padding)
+                    .unwrap(),
+            }
+        }
+
+        let extent = tile_bounds.size();
+        for x in 0..extent.w {
+            for y in 0..extent.h {
+                let idx = (tile_bounds.min.x + x) + (tile_bounds.min.y + y) * self.res.w;
+                pixel_samples[idx] += sample_counts[x + y * extent.w];
+                film_luminance_sums[idx] = luminance_sums[x + y * extent.w];
+            }
+        }
+    }
+
+    /// Copy the number of samples taken in each pixel into the `SampleCount` channel.
+    fn write_sample_counts(&mut self) {
+        let idx = match self.channel_indices.get(&ChannelKind::SampleCount) {
+            Some(&idx) => idx,
+            None => return,
+        };
+        let pixel_samples = self.pixel_samples.get_mut().unwrap();
+        if let ChannelStorage::SampleCount(buf) = &mut self.channels.get_mut().unwrap()[idx] {
+            for (value, count) in buf.iter_mut().zip(pixel_samples.iter()) {
+                *value = *count as f32;
+            }
+        }
+    }
+
+    /// Multiply every splatted channel back by the filter weights summed into each pixel.
+    fn denormalize_splats(&mut self) {
+        let channels = self.channels.get_mut().unwrap();
+        let weight_idx = match self.channel_indices.get(&ChannelKind::FilterWeight) {
+            Some(&idx) => idx,
+            None => return,
+        };
+        let weights = channel_storage_index!(channels, FilterWeight, weight_idx).clone();
+        for (i, channel) in channels.iter_mut().enumerate() {
+            if i != weight_idx {
+                channel.denormalize(&weights);
+            }
+        }
+    }
+
+    /// Divide every splatted channel by the filter weights summed into each pixel.
+    fn normalize_splats(&mut self) {
+        let channels = self.channels.get_mut().unwrap();
+        let weight_idx = match self.channel_indices.get(&ChannelKind::FilterWeight) {
+            Some(&idx) => idx,
+            None => return,
+        };
+        let weights = channel_storage_index!(channels, FilterWeight, weight_idx).clone();
+        for (i, channel) in channels.iter_mut().enumerate() {
+            if i != weight_idx {
+                channel.normalize(&weights);
+            }
+        }
+    }
+}
+
+/// Push the screen space motion, in pixels, of the first hit points of `intersection`'s
+/// rays from when the shutter opens for their scanline to when it closes. Motion vectors
+/// point right and up, and lanes whose points can't be seen at both times get none, so
+/// they don't count toward their pixel's average.
+#[allow(clippy::too_many_arguments)]
+fn push_motion_vectors(
+    world: &World,
+    camera: &dyn Camera,
+    shutter: &Shutter,
+    hitable: HitableHandle,
+    intersection: &WShadingPoint,
+    tile_min: Vec2u,
+    res: Extent2u,
+    output_samples: &mut BumpVec<(SampleCoord, ChannelSample)>,
+) {
+    let rays: [Ray; 4] = intersection.ray.into();
+    let scanlines = f32x4::from([
+        scanline_of(rays[0].tile_coord.pixel, tile_min, res),
+        scanline_of(rays[1].tile_coord.pixel, tile_min, res),
+        scanline_of(rays[2].tile_coord.pixel, tile_min, res),
+        scanline_of(rays[3].tile_coord.pixel, tile_min, res),
+    ]);
+    let (open, close) = shutter.scanline_times(scanlines);
+
+    let hitable = &world.hitables[hitable.0];
+    let time = intersection.ray.time;
+    let open_point = hitable.move_point(intersection.point, time, open);
+    let close_point = hitable.move_point(intersection.point, time, close);
+
+    let (open_uv, close_uv) = match (
+        camera.project(open_point, open),
+        camera.project(close_point, close),
+    ) {
+        (Some(open_uv), Some(close_uv)) => (open_uv, close_uv),
+        _ => return,
+    };
+
+    let motion = close_uv - open_uv;
+    let motion: [Vec2; 4] = motion.into();
+    for (ray, motion) in rays.iter().zip(motion.iter()) {
+        if ray.valid && motion.x.is_finite() && motion.y.is_finite() {
+            let mut motion = *motion;
+            // take the short way around when the point crosses the seam
+            if camera.wraps_horizontally() {
+                motion.x -= (motion.x + 0.5).floor();
+            }
+            let pixels = Vec2::new(motion.x * res.w as f32, motion.y * res.h as f32);
+            let sample = ChannelSample::MotionVector(Motion::new(pixels));
+            output_samples.push((ray.tile_coord, sample));
         }
     }
 }
 
+/// How far down from the top of the image the pixel at `tile_coord` is, as in
+/// `render_frame_into`.
+fn scanline_of(tile_coord: Vec2u, tile_min: Vec2u, res: Extent2u) -> f32 {
+    let y = tile_min.y + tile_coord.y;
+    (res.h as f32 - (y as f32 + 0.5)) / res.h as f32
+}
+
+/// Pick where in pixel (x, y) a sample is taken, returning its uv and its offset from the
+/// pixel's center in pixels. Without a filter importance sampler, samples are spread uniformly
+/// over the pixel.
 #[inline]
 fn sample_uv(
     x: usize,
     y: usize,
     screen_to_ndc_size: Vec2,
-    fis: &FilterImportanceSampler,
+    fis: Option<&FilterImportanceSampler>,
     samples: &[f32; 2],
-) -> Vec2 {
+) -> (Vec2, Vec2) {
     let uv_samp = Vec2::new(samples[0], samples[1]);
-    let fis_samp = Vec2::new(fis.sample(uv_samp.x), fis.sample(uv_samp.y));
+    let offset = match fis {
+        Some(fis) => Vec2::new(fis.sample(uv_samp.x), fis.sample(uv_samp.y)),
+        None => uv_samp - Vec2::new(0.5, 0.5),
+    };
 
-    let screen_coord = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) + fis_samp;
+    let screen_coord = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) + offset;
     // let screen_coord = Vec2::new(x as f32 + uv_samp.x, y as f32 + uv_samp.y);
 
-    screen_to_ndc_size * screen_coord
+    (screen_to_ndc_size * screen_coord, offset)
 }
@@ -184,6 +184,56 @@ impl Filter for LanczosSincFilter {
     }
 }
 
+/// Any of the filters, chosen at runtime.
+#[derive(Clone, Copy)]
+pub enum AnyFilter {
+    BlackmanHarris(BlackmanHarrisFilter),
+    MitchellNetravali(MitchellNetravaliFilter),
+    Box(BoxFilter),
+    LanczosSinc(LanczosSincFilter),
+}
+
+impl AnyFilter {
+    /// The default filter of the given name.
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "blackman-harris" => AnyFilter::BlackmanHarris(Default::default()),
+            "mitchell" => AnyFilter::MitchellNetravali(Default::default()),
+            "box" => AnyFilter::Box(Default::default()),
+            "lanczos" => AnyFilter::LanczosSinc(Default::default()),
+            _ => return Err(format!("Unknown filter: {}", name)),
+        })
+    }
+
+    /// Whether the filter has negative lobes, which only splatted reconstruction handles.
+    pub fn has_negative_lobes(&self) -> bool {
+        match self {
+            AnyFilter::BlackmanHarris(_) | AnyFilter::Box(_) => false,
+            AnyFilter::MitchellNetravali(_) | AnyFilter::LanczosSinc(_) => true,
+        }
+    }
+}
+
+impl Filter for AnyFilter {
+    fn radius(&self) -> f32 {
+        match self {
+            AnyFilter::BlackmanHarris(f) => f.radius(),
+            AnyFilter::MitchellNetravali(f) => f.radius(),
+            AnyFilter::Box(f) => f.radius(),
+            AnyFilter::LanczosSinc(f) => f.radius(),
+        }
+    }
+
+    fn evaluate(&self, p: f32) -> f32 {
+        match self {
+            AnyFilter::BlackmanHarris(f) => f.evaluate(p),
+            AnyFilter::MitchellNetravali(f) => Filter::evaluate(f, p),
+            AnyFilter::Box(f) => f.evaluate(p),
+            AnyFilter::LanczosSinc(f) => f.evaluate(p),
+        }
+    }
+}
+
 const FILTER_TABLE_SIZE: usize = 512;
 
 pub struct FilterImportanceSampler {
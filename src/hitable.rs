@@ -15,6 +15,12 @@ pub trait Hitable: Send + Sync {
         hits: WHit,
         half_pixel_size_at: &dyn Fn(f32x4) -> f32x4,
     ) -> (MaterialHandle, WShadingPoint);
+
+    /// Where a `point` on the surface at time `from` has moved to by time `to`. Surfaces
+    /// which don't move rigidly leave the point where it is.
+    fn move_point(&self, point: Wec3, _from: f32x4, _to: f32x4) -> Wec3 {
+        point
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -94,7 +100,7 @@ impl<'bump> HitStore<'bump> {
     pub fn process_hits(
         &mut self,
         hitables: &HitableStore,
-        wintersections: &mut BumpVec<'_, (MaterialHandle, WShadingPoint)>,
+        wintersections: &mut BumpVec<'_, (HitableHandle, MaterialHandle, WShadingPoint)>,
         half_pixel_size_at: &dyn Fn(f32x4) -> f32x4,
     ) {
         let total_hits = self
@@ -125,10 +131,9 @@ impl<'bump> HitStore<'bump> {
                         *hits.get_unchecked(3),
                     ]
                 });
-                wintersections.push(
-                    unsafe { hitables.get_unchecked(obj_id) }
-                        .get_shading_info(hits, half_pixel_size_at),
-                );
+                let (material, shading_point) = unsafe { hitables.get_unchecked(obj_id) }
+                    .get_shading_info(hits, half_pixel_size_at);
+                wintersections.push((HitableHandle(obj_id), material, shading_point));
             }
         }
     }
@@ -140,15 +145,38 @@ impl<'bump> HitStore<'bump> {
     }
 }
 
-pub struct HitableStore(Vec<Box<dyn Hitable>>);
+#[derive(Clone, Copy, Debug)]
+pub struct HitableHandle(pub usize);
+
+pub struct HitableStore {
+    hitables: Vec<Box<dyn Hitable>>,
+    names: Vec<String>,
+}
 
 impl HitableStore {
     pub fn new() -> Self {
-        HitableStore(Vec::new())
+        HitableStore {
+            hitables: Vec::new(),
+            names: Vec::new(),
+        }
+    }
+
+    pub fn push<S: Into<String>, H: Hitable + 'static>(
+        &mut self,
+        name: S,
+        hitable: H,
+    ) -> HitableHandle {
+        self.hitables.push(Box::new(hitable));
+        self.names.push(name.into());
+        HitableHandle(self.hitables.len() - 1)
+    }
+
+    pub fn name(&self, handle: HitableHandle) -> &str {
+        &self.names[handle.0]
     }
 
-    pub fn push<H: Hitable + 'static>(&mut self, hitable: H) {
-        self.0.push(Box::new(hitable))
+    pub fn names(&self) -> &[String] {
+        &self.names
     }
 }
 
@@ -156,7 +184,7 @@ impl ::std::ops::Deref for HitableStore {
     type Target = Vec<Box<dyn Hitable>>;
 
     fn deref(&self) -> &Vec<Box<dyn Hitable>> {
-        &self.0
+        &self.hitables
     }
 }
 
@@ -167,19 +195,20 @@ impl HitableStore {
         })
     }
 
-    pub fn add_hits(
+    /// Find the closest hitable along each lane of `ray`, returning the id of the hitable hit
+    /// (or `usize::MAX` on a miss) and the distance to it (or `t_max` on a miss).
+    fn closest(
         &self,
-        ray: WRay,
+        ray: &WRay,
         t_max: f32x4,
-        hit_store: &mut HitStore,
         half_pixel_size_at: &dyn Fn(f32x4) -> f32x4,
-    ) {
-        let (ids, dists) = self.iter().enumerate().fold(
+    ) -> ([usize; 4], f32x4) {
+        self.iter().enumerate().fold(
             ([std::usize::MAX; 4], t_max),
             |acc, (hitable_id, hitable)| {
                 let (mut closest_ids, mut closest) = acc;
 
-                let t = hitable.hit(&ray, closest, half_pixel_size_at);
+                let t = hitable.hit(ray, closest, half_pixel_size_at);
 
                 for ((t, closest), closest_id) in t
                     .as_ref()
@@ -195,7 +224,27 @@ impl HitableStore {
 
                 (closest_ids, closest)
             },
-        );
+        )
+    }
+
+    /// Distance along each lane of `ray` to the closest hit, or `t_max` if nothing was hit.
+    pub fn closest_hit(
+        &self,
+        ray: &WRay,
+        t_max: f32x4,
+        half_pixel_size_at: &dyn Fn(f32x4) -> f32x4,
+    ) -> f32x4 {
+        self.closest(ray, t_max, half_pixel_size_at).1
+    }
+
+    pub fn add_hits(
+        &self,
+        ray: WRay,
+        t_max: f32x4,
+        hit_store: &mut HitStore,
+        half_pixel_size_at: &dyn Fn(f32x4) -> f32x4,
+    ) {
+        let (ids, dists) = self.closest(&ray, t_max, half_pixel_size_at);
 
         let rays: [Ray; 4] = ray.into();
         let dists = dists.as_ref();
@@ -1,11 +1,14 @@
 use bumpalo::collections::Vec as BumpVec;
 use bumpalo::Bump;
 
-use crate::film::ChannelSample;
-use crate::hitable::WShadingPoint;
+use crate::camera::Camera;
+use crate::cryptomatte::{self, Coverage};
+use crate::film::{ChannelSample, Id};
+use crate::hitable::{HitableHandle, WShadingPoint};
+use crate::light::LightGroup;
 use crate::material::{MaterialHandle, BSDF};
-use crate::math::{f32x4, Vec2u, Vec3};
-use crate::ray::Ray;
+use crate::math::{f32x4, Vec3};
+use crate::ray::{Ray, SampleCoord, WRay};
 use crate::spectrum::{Srgb, WSrgb};
 use crate::world::World;
 
@@ -14,14 +17,16 @@ pub trait Integrator: Send + Sync {
     fn integrate(
         &self,
         world: &World,
+        camera: &dyn Camera,
         samples_1d: &[f32x4; 3],
         samples_2d: &[f32x4; 12],
         depth: usize,
+        hitable: HitableHandle,
         material: MaterialHandle,
         intersection: WShadingPoint,
         bump: &Bump,
         spawned_rays: &mut BumpVec<Ray>,
-        output_samples: &mut BumpVec<(Vec2u, ChannelSample)>,
+        output_samples: &mut BumpVec<(SampleCoord, ChannelSample)>,
     );
 
     fn requested_1d_sample_sets(&self) -> usize;
@@ -31,6 +36,78 @@ pub trait Integrator: Send + Sync {
 #[derive(Clone, Copy)]
 pub struct PathTracingIntegrator {
     pub max_bounces: usize,
+    /// output the `LightGroup`, `Direct`, `Indirect`, `Diffuse` and `Specular` channels
+    pub split_light_paths: bool,
+    /// the largest channel value a single sample of light reaching the first hit, or any
+    /// later hit, may contribute to a path. Clamping loses energy but keeps rare, very
+    /// bright paths from turning into fireflies
+    pub max_direct_radiance: Option<f32>,
+    pub max_indirect_radiance: Option<f32>,
+}
+
+impl PathTracingIntegrator {
+    /// The factor to scale `radiance` by so that its brightest channel is no more than the
+    /// clamp for light reaching a hit at `depth`.
+    fn clamp_scale(&self, depth: usize, radiance: WSrgb) -> f32x4 {
+        let max_radiance = if depth == 0 {
+            self.max_direct_radiance
+        } else {
+            self.max_indirect_radiance
+        };
+        match max_radiance {
+            Some(max_radiance) => {
+                (f32x4::from(max_radiance) / radiance.max_channel()).min(f32x4::ONE)
+            }
+            None => f32x4::ONE,
+        }
+    }
+
+    /// Push radiance arriving along `ray` into the light path channels. `lobes` splits
+    /// light reflected at the first hit into its (diffuse, specular) parts, while light
+    /// reaching later hits is split by the lobe the path first scattered off.
+    fn push_light_path_samples(
+        &self,
+        output_samples: &mut BumpVec<(SampleCoord, ChannelSample)>,
+        depth: usize,
+        ray: &WRay,
+        group: Option<LightGroup>,
+        radiance: WSrgb,
+        lobes: Option<(WSrgb, WSrgb)>,
+    ) {
+        if !self.split_light_paths {
+            return;
+        }
+
+        let radiance: [Srgb; 4] = radiance.into();
+        let lobes: Option<([Srgb; 4], [Srgb; 4])> =
+            lobes.map(|(diffuse, specular)| (diffuse.into(), specular.into()));
+
+        for i in 0..4 {
+            if !ray.valid[i] {
+                continue;
+            }
+            let coord = ray.tile_coord[i];
+
+            if let Some(group) = group {
+                output_samples.push((coord, ChannelSample::LightGroup(group.0, radiance[i])));
+            }
+
+            if depth == 0 {
+                output_samples.push((coord, ChannelSample::Direct(radiance[i])));
+                if let Some((diffuse, specular)) = lobes {
+                    output_samples.push((coord, ChannelSample::Diffuse(diffuse[i])));
+                    output_samples.push((coord, ChannelSample::Specular(specular[i])));
+                }
+            } else {
+                output_samples.push((coord, ChannelSample::Indirect(radiance[i])));
+                if ray.specular[i] {
+                    output_samples.push((coord, ChannelSample::Specular(radiance[i])));
+                } else {
+                    output_samples.push((coord, ChannelSample::Diffuse(radiance[i])));
+                }
+            }
+        }
+    }
 }
 
 impl Integrator for PathTracingIntegrator {
@@ -45,21 +122,64 @@ impl Integrator for PathTracingIntegrator {
     fn integrate(
         &self,
         world: &World,
+        camera: &dyn Camera,
         samples_1d: &[f32x4; 3],
         samples_2d: &[f32x4; 12],
         depth: usize,
+        hitable: HitableHandle,
         material: MaterialHandle,
         mut intersection: WShadingPoint,
         bump: &Bump,
         spawned_rays: &mut BumpVec<Ray>,
-        output_samples: &mut BumpVec<(Vec2u, ChannelSample)>,
+        output_samples: &mut BumpVec<(SampleCoord, ChannelSample)>,
     ) {
         let wo = -intersection.ray.dir;
+        let material_handle = material;
         let material = world.materials.get(material);
 
         let bsdf = material.get_bsdf_at(&intersection, bump);
 
-        intersection.ray.radiance += bsdf.le(wo, &intersection) * intersection.ray.throughput;
+        if depth == 0 {
+            let material_id = Id::new(material_handle.0);
+            let object_id = Id::new(hitable.0);
+            let crypto_material =
+                Coverage::new(cryptomatte::name_id(world.materials.name(material_handle)));
+            let crypto_object = Coverage::new(cryptomatte::name_id(world.hitables.name(hitable)));
+            // rays start at the camera's near clip distance
+            let dists = intersection.t + f32x4::from(camera.near_clip());
+            let rays: [Ray; 4] = intersection.ray.into();
+            let points: [Vec3; 4] = intersection.point.into();
+            // none of the BSDFs are perfectly specular, so the first hit is always the first
+            // non-specular hit
+            let albedos: [Srgb; 4] = bsdf.albedo().into();
+            for (((ray, dist), point), albedo) in rays
+                .iter()
+                .zip(dists.as_ref().iter())
+                .zip(points.iter())
+                .zip(albedos.iter())
+            {
+                if ray.valid {
+                    let coord = ray.tile_coord;
+                    output_samples.push((coord, ChannelSample::Depth(*dist)));
+                    output_samples.push((coord, ChannelSample::WorldPosition(*point)));
+                    output_samples.push((coord, ChannelSample::Albedo(*albedo)));
+                    output_samples.push((coord, ChannelSample::MaterialId(material_id)));
+                    output_samples.push((coord, ChannelSample::ObjectId(object_id)));
+                    output_samples.push((coord, ChannelSample::CryptoMaterial(crypto_material)));
+                    output_samples.push((coord, ChannelSample::CryptoObject(crypto_object)));
+                }
+            }
+        }
+
+        let emitted = bsdf.le(wo, &intersection) * intersection.ray.throughput;
+        // emission seen straight from the camera isn't noisy, so only light reaching a hit is
+        // clamped
+        let emitted = if depth > 0 {
+            emitted * self.clamp_scale(depth, emitted)
+        } else {
+            emitted
+        };
+        intersection.ray.radiance += emitted;
 
         if bsdf.receives_light() && world.lights.len() > 0 {
             let lights = (samples_1d[0] * f32x4::from(world.lights.len() as f32)).floor();
@@ -68,13 +188,26 @@ impl Integrator for PathTracingIntegrator {
 
             for (i, light_idx) in lights.enumerate() {
                 // let (i, light_idx) = (0, lights.next().unwrap());
-                intersection.ray.radiance += sample_one_light(
+                let (diffuse, specular) = sample_one_light(
                     world,
                     light_idx,
                     arrayref::array_ref![samples_2d, 0 + i * 2, 2],
                     &intersection,
                     bsdf,
                 );
+                // scale both lobes alike so that they still add up to the clamped total
+                let scale = self.clamp_scale(depth, diffuse + specular);
+                let (diffuse, specular) = (diffuse * scale, specular * scale);
+                intersection.ray.radiance += diffuse + specular;
+
+                self.push_light_path_samples(
+                    output_samples,
+                    depth,
+                    &intersection.ray,
+                    Some(world.lights.group_of(light_idx)),
+                    diffuse + specular,
+                    Some((diffuse, specular)),
+                );
             }
         }
 
@@ -85,6 +218,18 @@ impl Integrator for PathTracingIntegrator {
             arrayref::array_ref![samples_2d, 8, 4],
         );
 
+        // emission seen directly from the camera without scattering goes to the background
+        if depth > 0 || scattering_event.is_some() {
+            self.push_light_path_samples(
+                output_samples,
+                depth,
+                &intersection.ray,
+                world.materials.light_group(material_handle),
+                emitted,
+                None,
+            );
+        }
+
         if let Some(se) = scattering_event {
             let ndl = se.wi.dot(intersection.normal).abs();
 
@@ -106,7 +251,11 @@ impl Integrator for PathTracingIntegrator {
 
             if depth == 0 {
                 let normals: [Vec3; 4] = intersection.normal.into();
-                for (ray, normal) in new_rays.iter().zip(normals.iter()) {
+                let specular = se.specular.as_ref();
+                for ((ray, normal), specular) in
+                    new_rays.iter_mut().zip(normals.iter()).zip(specular.iter())
+                {
+                    ray.specular = specular.to_bits() != 0;
                     if ray.valid {
                         output_samples.push((ray.tile_coord, ChannelSample::Alpha(1.0)));
                         output_samples.push((ray.tile_coord, ChannelSample::WorldNormal(*normal)));
@@ -156,9 +305,13 @@ pub fn sample_one_light(
     samples: &[f32x4; 2],
     intersection: &WShadingPoint,
     bsdf: &dyn BSDF,
-) -> WSrgb {
-    let (end_point, li, pdf) =
-        world.lights[light_idx].sample(samples, intersection.point, intersection.normal);
+) -> (WSrgb, WSrgb) {
+    let (end_point, li, pdf) = world.lights[light_idx].sample(
+        samples,
+        intersection.point,
+        intersection.normal,
+        intersection.ray.time,
+    );
 
     let wo = -intersection.ray.dir;
     let wi = (end_point - intersection.point).normalized();
@@ -169,8 +322,12 @@ pub fn sample_one_light(
         .hitables
         .test_occluded(occlude_point, end_point, intersection.ray.time);
 
-    let f = bsdf.f(wo, wi, intersection.normal) * intersection.normal.dot(wi).max(f32x4::ZERO);
-    li * f * f32x4::from(world.lights.len() as f32 / 4.0) / pdf
+    let (diffuse_f, specular_f) = bsdf.f_lobes(wo, wi, intersection.normal);
+    let factor = li
+        * intersection.normal.dot(wi).max(f32x4::ZERO)
+        * f32x4::from(world.lights.len() as f32 / 4.0)
+        / pdf
         * intersection.ray.throughput
-        * occluded
+        * occluded;
+    (diffuse_f * factor, specular_f * factor)
 }
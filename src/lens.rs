@@ -0,0 +1,610 @@
+use crate::animation::WSequenced;
+use crate::camera::Camera;
+use crate::math::{f32x4, Vec2, Vec2u, Vec3, Wec2, Wec3};
+use crate::ray::{Ray, WRay};
+use crate::spectrum::Srgb;
+
+use std::path::Path;
+
+/// A double Gauss 50mm f/2 lens (US patent 2,673,491), as shipped with pbrt.
+pub const DOUBLE_GAUSS_50MM: &str = "
+# radius  thickness  ior    aperture
+29.475    3.76       1.67   25.2
+84.83     0.12       1      25.2
+19.275    4.025      1.67   23
+40.77     3.275      1.699  23
+12.75     5.705      1      18
+0         4.5        0      17.1
+-14.495   1.18       1.603  17
+40.77     6.065      1.658  20
+-20.385   0.19       1      20
+437.065   3.22       1.717  20
+-39.73    5.0        1      20
+";
+
+// number of film radii the exit pupil bounds are precomputed for
+const EXIT_PUPIL_BOUNDS: usize = 64;
+// rays traced per film radius when bounding the exit pupil
+const EXIT_PUPIL_SAMPLES: usize = 4096;
+
+/// A single spherical (or, with zero curvature, planar aperture stop) lens surface, in meters.
+#[derive(Clone, Copy, Debug)]
+pub struct LensElement {
+    pub curvature_radius: f32,
+    pub thickness: f32,
+    pub ior: f32,
+    pub aperture_radius: f32,
+}
+
+impl LensElement {
+    fn is_stop(&self) -> bool {
+        self.curvature_radius == 0.0
+    }
+}
+
+/// A lens prescription: the sequence of lens surfaces ordered from the scene side to the
+/// film side.
+#[derive(Clone, Debug)]
+pub struct LensPrescription {
+    elements: Vec<LensElement>,
+}
+
+impl LensPrescription {
+    /// Parse a prescription in pbrt's lens format: one surface per line, given as curvature
+    /// radius, thickness, index of refraction and aperture diameter, all in millimeters. A
+    /// curvature radius of 0 marks the aperture stop. Lines starting with `#` are comments.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut elements = Vec::new();
+
+        for (line_idx, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Line {}: {}", line_idx + 1, e))?;
+
+            if values.len() != 4 {
+                return Err(format!(
+                    "Line {}: expected 4 values but found {}",
+                    line_idx + 1,
+                    values.len()
+                ));
+            }
+
+            elements.push(LensElement {
+                curvature_radius: values[0] * 0.001,
+                thickness: values[1] * 0.001,
+                ior: values[2],
+                aperture_radius: values[3] * 0.001 / 2.0,
+            });
+        }
+
+        if elements.is_empty() {
+            return Err(String::from("Lens prescription has no elements"));
+        }
+
+        Ok(LensPrescription { elements })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path.as_ref()).map_err(|e| {
+            format!(
+                "Failed to read lens file {}: {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+        Self::parse(&source)
+    }
+
+    /// Stop the lens down to `diameter` millimeters. Has no effect if the aperture stop is
+    /// already smaller than that.
+    #[allow(dead_code)]
+    pub fn with_aperture_diameter(mut self, diameter: f32) -> Self {
+        for element in self.elements.iter_mut().filter(|e| e.is_stop()) {
+            element.aperture_radius = element.aperture_radius.min(diameter * 0.001 / 2.0);
+        }
+        self
+    }
+}
+
+/// Axis aligned bounds on the plane of the rear lens element.
+#[derive(Clone, Copy, Debug)]
+struct Bounds2 {
+    min: Vec2,
+    max: Vec2,
+}
+
+impl Bounds2 {
+    fn empty() -> Self {
+        Bounds2 {
+            min: Vec2::new(std::f32::MAX, std::f32::MAX),
+            max: Vec2::new(std::f32::MIN, std::f32::MIN),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y
+    }
+
+    fn inside(&self, p: Vec2) -> bool {
+        p.x >= self.min.x && p.x <= self.max.x && p.y >= self.min.y && p.y <= self.max.y
+    }
+
+    fn union(&mut self, p: Vec2) {
+        self.min = Vec2::new(self.min.x.min(p.x), self.min.y.min(p.y));
+        self.max = Vec2::new(self.max.x.max(p.x), self.max.y.max(p.y));
+    }
+
+    fn expand(&mut self, by: f32) {
+        self.min -= Vec2::new(by, by);
+        self.max += Vec2::new(by, by);
+    }
+
+    fn lerp(&self, t: Vec2) -> Vec2 {
+        Vec2::new(
+            self.min.x + (self.max.x - self.min.x) * t.x,
+            self.min.y + (self.max.y - self.min.y) * t.y,
+        )
+    }
+
+    fn area(&self) -> f32 {
+        (self.max.x - self.min.x) * (self.max.y - self.min.y)
+    }
+}
+
+/// A ray in lens space: the optical axis is z, the film sits at z = 0 and the lens
+/// extends towards negative z.
+#[derive(Clone, Copy, Debug)]
+struct LensRay {
+    origin: Vec3,
+    dir: Vec3,
+}
+
+impl LensRay {
+    fn point_at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+}
+
+fn radical_inverse(base: usize, mut i: usize) -> f32 {
+    let inv_base = 1.0 / base as f32;
+    let mut inv_bi = inv_base;
+    let mut result = 0.0;
+    while i > 0 {
+        result += (i % base) as f32 * inv_bi;
+        i /= base;
+        inv_bi *= inv_base;
+    }
+    result
+}
+
+fn refract(wi: Vec3, n: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_theta_i = n.dot(wi);
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = eta * eta * sin2_theta_i;
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-wi * eta + n * (eta * cos_theta_i - cos_theta_t))
+}
+
+/// Intersect a ray with a spherical lens surface of `radius` centered on the axis at
+/// `z_center`, returning the distance to the hit and the surface normal facing the ray.
+fn intersect_spherical_element(radius: f32, z_center: f32, ray: &LensRay) -> Option<(f32, Vec3)> {
+    let o = ray.origin - Vec3::new(0.0, 0.0, z_center);
+    let a = ray.dir.mag_sq();
+    let b = 2.0 * ray.dir.dot(o);
+    let c = o.mag_sq() - radius * radius;
+
+    let descrim = b * b - 4.0 * a * c;
+    if descrim < 0.0 {
+        return None;
+    }
+    let root = descrim.sqrt();
+    let q = if b < 0.0 {
+        -0.5 * (b - root)
+    } else {
+        -0.5 * (b + root)
+    };
+    let (t0, t1) = (q / a, c / q);
+    let (t0, t1) = if t0 > t1 { (t1, t0) } else { (t0, t1) };
+
+    let use_closer_t = (ray.dir.z > 0.0) ^ (radius < 0.0);
+    let t = if use_closer_t { t0 } else { t1 };
+    if !(t >= 0.0) {
+        return None;
+    }
+
+    let n = (o + ray.dir * t).normalized();
+    let n = if n.dot(-ray.dir) < 0.0 { -n } else { n };
+    Some((t, n))
+}
+
+/// A camera which traces rays through a real lens prescription, following pbrt's
+/// `RealisticCamera`. This gives the vignetting, distortion and focus breathing of the
+/// actual lens, at the cost of losing some rays which don't make it through.
+pub struct RealisticCamera<O, A, U> {
+    elements: Vec<LensElement>,
+    // physical extent of the film, in meters
+    film_half_size: Vec2,
+    film_diagonal: f32,
+    exit_pupil_bounds: Vec<Bounds2>,
+    half_pixel_size: f32x4,
+    origin: O,
+    at: A,
+    up: U,
+}
+
+impl<O, A, U> RealisticCamera<O, A, U> {
+    /// Create a camera whose film has a diagonal of `film_diagonal` millimeters (35mm film is
+    /// about 43.3), with the lens focused at `focus_distance` from the film.
+    #[allow(dead_code)]
+    pub fn new(
+        resolution: Vec2,
+        lens: LensPrescription,
+        film_diagonal: f32,
+        focus_distance: f32,
+        origin: O,
+        at: A,
+        up: U,
+    ) -> Result<Self, String> {
+        let film_diagonal = film_diagonal * 0.001;
+        let aspect = resolution.x / resolution.y;
+        let film_height = (film_diagonal * film_diagonal / (1.0 + aspect * aspect)).sqrt();
+        let film_half_size = Vec2::new(aspect * film_height, film_height) / 2.0;
+
+        let mut camera = RealisticCamera {
+            elements: lens.elements,
+            film_half_size,
+            film_diagonal,
+            exit_pupil_bounds: Vec::new(),
+            half_pixel_size: f32x4::ZERO,
+            origin,
+            at,
+            up,
+        };
+
+        let (pz, fz) = camera.thick_lens_approximation()?;
+        let focal_length = (fz[0] - pz[0]).abs();
+        camera.half_pixel_size = f32x4::from(film_height / resolution.y / 2.0 / focal_length);
+
+        let rear_thickness = camera.focus_thick_lens(pz, fz, focus_distance)?;
+        camera.elements.last_mut().unwrap().thickness = rear_thickness;
+
+        camera.exit_pupil_bounds = (0..EXIT_PUPIL_BOUNDS)
+            .map(|i| {
+                let r0 = i as f32 / EXIT_PUPIL_BOUNDS as f32 * film_diagonal / 2.0;
+                let r1 = (i + 1) as f32 / EXIT_PUPIL_BOUNDS as f32 * film_diagonal / 2.0;
+                camera.bound_exit_pupil(r0, r1)
+            })
+            .collect();
+
+        Ok(camera)
+    }
+
+    fn lens_rear_z(&self) -> f32 {
+        self.elements.last().unwrap().thickness
+    }
+
+    fn lens_front_z(&self) -> f32 {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    fn rear_element_radius(&self) -> f32 {
+        self.elements.last().unwrap().aperture_radius
+    }
+
+    /// Trace a ray (in camera space, with the lens towards +z) from the film out through
+    /// the lens, returning the exiting ray or `None` if it is blocked.
+    fn trace_from_film(&self, ray: &LensRay) -> Option<LensRay> {
+        let mut element_z = 0.0;
+        let mut lens_ray = LensRay {
+            origin: Vec3::new(ray.origin.x, ray.origin.y, -ray.origin.z),
+            dir: Vec3::new(ray.dir.x, ray.dir.y, -ray.dir.z),
+        };
+
+        for i in (0..self.elements.len()).rev() {
+            let element = &self.elements[i];
+            element_z -= element.thickness;
+
+            let (t, n) = if element.is_stop() {
+                (
+                    (element_z - lens_ray.origin.z) / lens_ray.dir.z,
+                    Vec3::zero(),
+                )
+            } else {
+                let radius = element.curvature_radius;
+                intersect_spherical_element(radius, element_z + radius, &lens_ray)?
+            };
+
+            let hit = lens_ray.point_at(t);
+            if hit.x * hit.x + hit.y * hit.y > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+            lens_ray.origin = hit;
+
+            if !element.is_stop() {
+                let eta_i = element.ior;
+                let eta_t = if i > 0 && self.elements[i - 1].ior != 0.0 {
+                    self.elements[i - 1].ior
+                } else {
+                    1.0
+                };
+                lens_ray.dir = refract(-lens_ray.dir.normalized(), n, eta_i / eta_t)?;
+            }
+        }
+
+        Some(LensRay {
+            origin: Vec3::new(lens_ray.origin.x, lens_ray.origin.y, -lens_ray.origin.z),
+            dir: Vec3::new(lens_ray.dir.x, lens_ray.dir.y, -lens_ray.dir.z),
+        })
+    }
+
+    /// Trace a ray (in camera space) from the scene in through the lens towards the film.
+    fn trace_from_scene(&self, ray: &LensRay) -> Option<LensRay> {
+        let mut element_z = -self.lens_front_z();
+        let mut lens_ray = LensRay {
+            origin: Vec3::new(ray.origin.x, ray.origin.y, -ray.origin.z),
+            dir: Vec3::new(ray.dir.x, ray.dir.y, -ray.dir.z),
+        };
+
+        for i in 0..self.elements.len() {
+            let element = &self.elements[i];
+
+            let (t, n) = if element.is_stop() {
+                (
+                    (element_z - lens_ray.origin.z) / lens_ray.dir.z,
+                    Vec3::zero(),
+                )
+            } else {
+                let radius = element.curvature_radius;
+                intersect_spherical_element(radius, element_z + radius, &lens_ray)?
+            };
+
+            let hit = lens_ray.point_at(t);
+            if hit.x * hit.x + hit.y * hit.y > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+            lens_ray.origin = hit;
+
+            if !element.is_stop() {
+                let eta_i = if i == 0 || self.elements[i - 1].ior == 0.0 {
+                    1.0
+                } else {
+                    self.elements[i - 1].ior
+                };
+                let eta_t = if element.ior != 0.0 { element.ior } else { 1.0 };
+                lens_ray.dir = refract(-lens_ray.dir.normalized(), n, eta_i / eta_t)?;
+            }
+
+            element_z += element.thickness;
+        }
+
+        Some(LensRay {
+            origin: Vec3::new(lens_ray.origin.x, lens_ray.origin.y, -lens_ray.origin.z),
+            dir: Vec3::new(lens_ray.dir.x, lens_ray.dir.y, -lens_ray.dir.z),
+        })
+    }
+
+    /// Find the z of the principal plane and focal point from a ray parallel to the axis
+    /// entering the lens and the ray that exits.
+    fn cardinal_points(ray_in: &LensRay, ray_out: &LensRay) -> (f32, f32) {
+        let tf = -ray_out.origin.x / ray_out.dir.x;
+        let fz = -ray_out.point_at(tf).z;
+        let tp = (ray_in.origin.x - ray_out.origin.x) / ray_out.dir.x;
+        let pz = -ray_out.point_at(tp).z;
+        (pz, fz)
+    }
+
+    /// Principal plane and focal point positions for the scene and film sides of the lens.
+    fn thick_lens_approximation(&self) -> Result<([f32; 2], [f32; 2]), String> {
+        let x = 0.001 * self.film_diagonal;
+
+        let scene_ray = LensRay {
+            origin: Vec3::new(x, 0.0, self.lens_front_z() + 1.0),
+            dir: Vec3::new(0.0, 0.0, -1.0),
+        };
+        let film_ray = self.trace_from_scene(&scene_ray).ok_or_else(|| {
+            String::from("Unable to trace a paraxial ray from the scene through the lens")
+        })?;
+        let (pz0, fz0) = Self::cardinal_points(&scene_ray, &film_ray);
+
+        let film_ray = LensRay {
+            origin: Vec3::new(x, 0.0, self.lens_rear_z() - 1.0),
+            dir: Vec3::new(0.0, 0.0, 1.0),
+        };
+        let scene_ray = self.trace_from_film(&film_ray).ok_or_else(|| {
+            String::from("Unable to trace a paraxial ray from the film through the lens")
+        })?;
+        let (pz1, fz1) = Self::cardinal_points(&film_ray, &scene_ray);
+
+        Ok(([pz0, pz1], [fz0, fz1]))
+    }
+
+    /// The distance between the rear element and the film which focuses the lens at
+    /// `focus_distance`.
+    fn focus_thick_lens(
+        &self,
+        pz: [f32; 2],
+        fz: [f32; 2],
+        focus_distance: f32,
+    ) -> Result<f32, String> {
+        let f = fz[0] - pz[0];
+        let z = -focus_distance;
+        let c = (pz[1] - z - pz[0]) * (pz[1] - z - 4.0 * f - pz[0]);
+        if c <= 0.0 {
+            return Err(format!(
+                "Lens with focal length {}m can't focus at {}m",
+                fz[0] - pz[0],
+                focus_distance
+            ));
+        }
+        let delta = 0.5 * (pz[1] - z + pz[0] - c.sqrt());
+        Ok(self.lens_rear_z() + delta)
+    }
+
+    /// Bound the area of the rear element through which rays from film points between radius
+    /// `r0` and `r1` from the center can leave the lens.
+    fn bound_exit_pupil(&self, r0: f32, r1: f32) -> Bounds2 {
+        let rear_radius = self.rear_element_radius();
+        let proj_rear_bounds = Bounds2 {
+            min: Vec2::new(-1.5 * rear_radius, -1.5 * rear_radius),
+            max: Vec2::new(1.5 * rear_radius, 1.5 * rear_radius),
+        };
+
+        let mut pupil_bounds = Bounds2::empty();
+        for i in 0..EXIT_PUPIL_SAMPLES {
+            let a = (i as f32 + 0.5) / EXIT_PUPIL_SAMPLES as f32;
+            let film_point = Vec3::new(r0 + (r1 - r0) * a, 0.0, 0.0);
+            let rear_point =
+                proj_rear_bounds.lerp(Vec2::new(radical_inverse(2, i), radical_inverse(3, i)));
+            let rear_point_3 = Vec3::new(rear_point.x, rear_point.y, self.lens_rear_z());
+
+            if pupil_bounds.inside(rear_point)
+                || self
+                    .trace_from_film(&LensRay {
+                        origin: film_point,
+                        dir: rear_point_3 - film_point,
+                    })
+                    .is_some()
+            {
+                pupil_bounds.union(rear_point);
+            }
+        }
+
+        if pupil_bounds.is_empty() {
+            return proj_rear_bounds;
+        }
+
+        let diagonal = proj_rear_bounds.max - proj_rear_bounds.min;
+        pupil_bounds.expand(2.0 * diagonal.mag() / (EXIT_PUPIL_SAMPLES as f32).sqrt());
+        pupil_bounds
+    }
+
+    /// Sample a point on the rear element within the exit pupil for `film_point`, returning
+    /// it along with the area of the bounds it was sampled from.
+    fn sample_exit_pupil(&self, film_point: Vec2, lens_sample: Vec2) -> (Vec3, f32) {
+        let r_film = film_point.mag();
+        let r_index = ((r_film / (self.film_diagonal / 2.0) * EXIT_PUPIL_BOUNDS as f32) as usize)
+            .min(EXIT_PUPIL_BOUNDS - 1);
+        let pupil_bounds = self.exit_pupil_bounds[r_index];
+        let lens_point = pupil_bounds.lerp(lens_sample);
+
+        // the bounds were computed along +x, so rotate them to line up with the film point
+        let (sin_theta, cos_theta) = if r_film != 0.0 {
+            (film_point.y / r_film, film_point.x / r_film)
+        } else {
+            (0.0, 1.0)
+        };
+
+        (
+            Vec3::new(
+                cos_theta * lens_point.x - sin_theta * lens_point.y,
+                sin_theta * lens_point.x + cos_theta * lens_point.y,
+                self.lens_rear_z(),
+            ),
+            pupil_bounds.area(),
+        )
+    }
+}
+
+impl<O, A, U> Camera for RealisticCamera<O, A, U>
+where
+    O: WSequenced<Wec3>,
+    A: WSequenced<Wec3>,
+    U: WSequenced<Wec3>,
+{
+    fn get_rays(
+        &self,
+        scramble: f32,
+        sample_nums: [usize; 4],
+        tile_coord: Vec2u,
+        uv: Wec2,
+        time: f32x4,
+        samples: &[f32x4; 2],
+    ) -> WRay {
+        let origin = self.origin.sample_at(time);
+        let at = self.at.sample_at(time);
+        let up = self.up.sample_at(time);
+
+        let basis_w = (origin - at).normalized();
+        let basis_u = up.cross(basis_w).normalized();
+        let basis_v = basis_w.cross(basis_u);
+
+        let origins: [Vec3; 4] = origin.into();
+        let basis_u: [Vec3; 4] = basis_u.into();
+        let basis_v: [Vec3; 4] = basis_v.into();
+        let basis_w: [Vec3; 4] = basis_w.into();
+
+        let times = time.as_ref();
+        let us = uv.x.as_ref();
+        let vs = uv.y.as_ref();
+        let lens_us = samples[0].as_ref();
+        let lens_vs = samples[1].as_ref();
+
+        let full_pupil_area = self.exit_pupil_bounds[0].area();
+
+        let mut rays = [Ray::new_invalid(); 4];
+        for (i, ray) in rays.iter_mut().enumerate() {
+            // rays blocked by the lens housing stay invalid, but keep a sensible time and
+            // origin so that the rest of the lanes can be traced as normal
+            *ray = Ray::new(
+                origins[i],
+                -basis_w[i],
+                times[i],
+                tile_coord,
+                scramble,
+                sample_nums[i],
+            );
+            ray.valid = false;
+            ray.throughput = Srgb::zero();
+
+            // the lens flips the image, so the film is flipped to match
+            let film_point = Vec2::new(
+                self.film_half_size.x * (1.0 - 2.0 * us[i]),
+                self.film_half_size.y * (1.0 - 2.0 * vs[i]),
+            );
+            let (rear_point, pupil_area) =
+                self.sample_exit_pupil(film_point, Vec2::new(lens_us[i], lens_vs[i]));
+
+            let film_ray = LensRay {
+                origin: Vec3::new(film_point.x, film_point.y, 0.0),
+                dir: rear_point - Vec3::new(film_point.x, film_point.y, 0.0),
+            };
+
+            let lens_ray = match self.trace_from_film(&film_ray) {
+                Some(lens_ray) => lens_ray,
+                None => continue,
+            };
+
+            // camera space looks down +z, the world basis looks down -w
+            let to_world = |v: Vec3| basis_u[i] * v.x + basis_v[i] * v.y - basis_w[i] * v.z;
+
+            let cos_theta = film_ray.dir.normalized().z;
+            let cos4_theta = cos_theta * cos_theta * cos_theta * cos_theta;
+
+            *ray = Ray::new(
+                origins[i] + to_world(lens_ray.origin),
+                to_world(lens_ray.dir).normalized(),
+                times[i],
+                tile_coord,
+                scramble,
+                sample_nums[i],
+            );
+            ray.throughput = Srgb::one() * (cos4_theta * pupil_area / full_pupil_area);
+        }
+
+        WRay::from(rays)
+    }
+
+    fn half_pixel_size_at(&self, t: f32x4) -> f32x4 {
+        self.half_pixel_size * t
+    }
+}
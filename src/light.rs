@@ -1,31 +1,85 @@
-use crate::math::{f32x4, OrthonormalBasis, Vec3, Wec3};
+use crate::animation::WSequenced;
+use crate::math::{f32x4, OrthonormalBasis, Wec3};
 use crate::spectrum::{Srgb, WSrgb};
 
 pub trait Light: Send + Sync {
     // returns (sampled point, output radiance toward ref, pdf of sample wrt solid angle wrt ref point)
-    fn sample(&self, samples: &[f32x4; 2], point: Wec3, normal: Wec3) -> (Wec3, WSrgb, f32x4);
+    fn sample(
+        &self,
+        samples: &[f32x4; 2],
+        point: Wec3,
+        normal: Wec3,
+        time: f32x4,
+    ) -> (Wec3, WSrgb, f32x4);
+}
+
+/// Lights which can be rendered into a separate `LightGroup` channel together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LightGroup(pub usize);
+
+pub struct LightStore {
+    lights: Vec<Box<dyn Light>>,
+    light_groups: Vec<LightGroup>,
+    group_names: Vec<String>,
+}
+
+impl LightStore {
+    pub fn new() -> Self {
+        LightStore {
+            lights: Vec::new(),
+            light_groups: Vec::new(),
+            group_names: Vec::new(),
+        }
+    }
+
+    pub fn add_group<S: Into<String>>(&mut self, name: S) -> LightGroup {
+        self.group_names.push(name.into());
+        LightGroup(self.group_names.len() - 1)
+    }
+
+    pub fn push<L: Light + 'static>(&mut self, group: LightGroup, light: L) {
+        self.lights.push(Box::new(light));
+        self.light_groups.push(group);
+    }
+
+    pub fn group_of(&self, light_idx: usize) -> LightGroup {
+        self.light_groups[light_idx]
+    }
+
+    pub fn group_names(&self) -> &[String] {
+        &self.group_names
+    }
+}
+
+impl ::std::ops::Deref for LightStore {
+    type Target = Vec<Box<dyn Light>>;
+
+    fn deref(&self) -> &Vec<Box<dyn Light>> {
+        &self.lights
+    }
 }
 
 #[derive(Clone, Copy)]
-pub struct SphereLight {
-    pos: Wec3,
+pub struct SphereLight<P> {
+    pos: P,
     emission: WSrgb,
     rad: f32x4,
 }
 
-impl SphereLight {
-    pub fn new(pos: Vec3, rad: f32, emission: Srgb) -> Self {
+impl<P> SphereLight<P> {
+    pub fn new(pos: P, rad: f32, emission: Srgb) -> Self {
         Self {
-            pos: Wec3::splat(pos),
+            pos,
             emission: WSrgb::splat(emission),
             rad: f32x4::from(rad),
         }
     }
 }
 
-impl Light for SphereLight {
-    fn sample(&self, samples: &[f32x4; 2], p: Wec3, _n: Wec3) -> (Wec3, WSrgb, f32x4) {
-        let dir = self.pos - p;
+impl<P: WSequenced<Wec3>> Light for SphereLight<P> {
+    fn sample(&self, samples: &[f32x4; 2], p: Wec3, _n: Wec3, time: f32x4) -> (Wec3, WSrgb, f32x4) {
+        let pos = self.pos.sample_at(time);
+        let dir = pos - p;
         let dist2 = dir.mag_sq();
         let dist = dist2.sqrt();
         let dir = dir / dist;
@@ -49,7 +103,7 @@ impl Light for SphereLight {
             + basis.cols[1] * sin_alpha * sin_phi
             + basis.cols[2] * cos_alpha;
 
-        let point = self.pos + offset * self.rad;
+        let point = pos + offset * self.rad;
 
         let pdf = uniform_cone_pdf(cos_theta_max);
 
@@ -1,81 +1,140 @@
-use generic_array::typenum::*;
-
 mod animation;
+mod blue_noise;
 mod camera;
+mod checkpoint;
+mod cryptomatte;
+mod denoise;
+mod exr;
 mod film;
 mod filter;
 mod hitable;
 mod integrator;
+mod lens;
 mod light;
 mod material;
 mod math;
+mod post;
 mod ray;
 mod sampler;
 mod sdf;
+mod shutter;
 mod spectrum;
 mod sphere;
 mod world;
 
-use camera::{CameraHandle, CameraStore, PinholeCamera};
-use film::{ChannelKind, Film};
-use filter::BlackmanHarrisFilter;
+use animation::{Animation, Sequenced};
+use blue_noise::BlueNoiseMask;
+use camera::{
+    ApertureMask, ApertureShape, Camera, CameraHandle, CameraStore, ClippedCamera,
+    EquirectangularCamera, FisheyeCamera, FisheyeProjection, OdsCamera, PinholeCamera, StereoEye,
+    StereoLayout, ThinLensCamera,
+};
+use denoise::Denoiser;
+use film::{AdaptiveSampling, ChannelKind, Film, Reconstruction};
+use filter::{AnyFilter, BlackmanHarrisFilter};
 use hitable::HitableStore;
 use integrator::PathTracingIntegrator;
-use light::{Light, SphereLight};
+use lens::{LensPrescription, RealisticCamera, DOUBLE_GAUSS_50MM};
+use light::{LightStore, SphereLight};
 use material::{Dielectric, MaterialStore, Sky};
 // use material::Emissive;
 use math::{Extent2u, Vec2, Vec3};
-use sdf::{BoxFold, MandelBox, SphereFold, TracedSDF};
+use post::{Bloom, ChromaticAberration, FilmGrain, Glare, Vignette};
+use sampler::{AnySampler, PixelRandomization, Sampler};
+use sdf::{AnimatedMandelBox, TracedSDF};
+use shutter::{Shutter, ShutterCurve};
 use spectrum::Srgb;
 use sphere::Sphere;
 use world::World;
 
 // use sdfu::SDF;
 
+use std::ops::Range;
+use std::path::PathBuf;
 use std::time::Instant;
 
 const RES: (usize, usize) = (1920, 1080);
 const SAMPLES: usize = 1;
-const WORLD_RADIUS: f32 = 100.0;
 
 // closer to 0 = smaller detail will be shown. larger means less detail.
 const SDF_DETAIL_SCALE: f32 = 10.0;
 
-fn setup() -> (CameraHandle, World) {
+/// Build the scene, binding any tracks in `animation` to the properties they name.
+///
+/// Bindable properties:
+/// * `camera.origin`, `camera.at`, `camera.up`, `camera.focus` (vec3) and `camera.aperture`
+///   (scalar). Binding `camera.aperture` switches a perspective camera to a thin lens camera,
+///   whose focus is found automatically with `--autofocus` or `--autofocus-at <u> <v>`.
+///   `camera.focus` also sets the focus distance of the realistic lens camera, at time 0.
+///   These all apply to the `main` camera.
+/// * `overhead.origin`, `overhead.at`, `overhead.up`, `side.origin`, `side.at` and `side.up`
+///   (vec3) for the alternate `overhead` and `side` cameras
+/// * `sun.position`, `pink_fill.<n>.position` and `blue_fill.<n>.position` (vec3)
+/// * `mandelbox.scale`, `mandelbox.box_fold`, `mandelbox.sphere_fold.min_radius` and
+///   `mandelbox.sphere_fold.fixed_radius` (scalar)
+fn setup(animation: &Animation, options: &Options) -> Result<World, String> {
     let mut materials = MaterialStore::new();
     let mut hitables = HitableStore::new();
-    let mut lights: Vec<Box<dyn Light>> = Vec::new();
+    let mut lights = LightStore::new();
+    let world_radius = 100.0;
 
     // SKY
-    let sky = materials.add_material(Sky::new(
-        Srgb::new(0.3, 0.2, 0.6) * 2.5,
-        Srgb::new(0.5, 0.3, 0.6) * 1.0,
-    ));
+    let sky = materials.add_material(
+        "sky",
+        Sky::new(
+            Srgb::new(0.3, 0.2, 0.6) * 2.5,
+            Srgb::new(0.5, 0.3, 0.6) * 1.0,
+        ),
+    );
+
+    hitables.push(
+        "sky",
+        Sphere::new(Vec3::new(0.0, 0.0, 0.0), world_radius, sky),
+    );
 
-    hitables.push(Sphere::new(Vec3::new(0.0, 0.0, 0.0), WORLD_RADIUS, sky));
+    let sky_group = lights.add_group("sky");
+    materials.set_light_group(sky, sky_group);
 
     // FRACTAL
-    let grey = materials.add_material(Dielectric::new_remap(Srgb::new(0.2, 0.2, 0.2), 0.6));
+    let grey = materials.add_material("grey", Dielectric::new_remap(Srgb::new(0.2, 0.2, 0.2), 0.6));
 
-    hitables.push(TracedSDF::new(
-        // MandelBox::new(MB_ITERS, BoxFold::new(1.0), SphereFold::new(0.5, 1.0), -2.0)
-        MandelBox::new(12, BoxFold::new(1.5), SphereFold::new(0.1, 1.5), -2.25),
+    let scale = animation.track_or("mandelbox.scale", -2.25)?;
+    let box_fold = animation.track_or("mandelbox.box_fold", 1.5)?;
+    let min_radius = animation.track_or("mandelbox.sphere_fold.min_radius", 0.1)?;
+    let fixed_radius = animation.track_or("mandelbox.sphere_fold.fixed_radius", 1.5)?;
+
+    hitables.push(
+        "mandelbox",
+        TracedSDF::new(
+            // MandelBox::new(MB_ITERS, BoxFold::new(1.0), SphereFold::new(0.5, 1.0), -2.0)
+            AnimatedMandelBox::new(12, box_fold, min_radius, fixed_radius, scale),
             // .subtract(sdfu::Sphere::new(ultraviolet::f32x4::from(2.25)).translate(ultraviolet::Wec3::new_splat(0.0, 0.0, 2.0))),
-        grey,
-    ));
+            grey,
+        ),
+    );
 
     // SUN
     let bluesun = Srgb::new(1.5, 3.0, 5.0) * 5000.0;
-    lights.push(Box::new(SphereLight::new(
-        Vec3::new(-1.0, 2.65, 1.5).normalized() * 99.0,
-        1.0,
-        bluesun,
-    )));
+    let sun_group = lights.add_group("sun");
+    lights.push(
+        sun_group,
+        SphereLight::new(
+            animation.track_or(
+                "sun.position",
+                Vec3::new(-1.0, 2.65, 1.5).normalized() * 99.0,
+            )?,
+            1.0,
+            bluesun,
+        ),
+    );
 
     let pink = Srgb::new(4.5, 1.5, 3.0) * 4.0;
     let blue = Srgb::new(1.5, 3.0, 4.5) * 4.0;
-    // let blue_emissive = materials.add_material(Emissive::new_splat(blue));
-    // let pink_emissive = materials.add_material(Emissive::new_splat(pink));
+    // let blue_emissive = materials.add_material("blue_emissive", Emissive::new_splat(blue));
+    // let pink_emissive = materials.add_material("pink_emissive", Emissive::new_splat(pink));
+
+    let pink_group = lights.add_group("pink_fills");
+    let blue_group = lights.add_group("blue_fills");
 
     let light_pairs = [
         (Vec3::new(0.0, 0.6, 2.5), 0.15),
@@ -84,25 +143,31 @@ fn setup() -> (CameraHandle, World) {
         (Vec3::new(2.5, -0.6, 0.0), 0.15),
     ];
 
-    for &(pos, rad) in light_pairs.iter() {
+    for (i, &(pos, rad)) in light_pairs.iter().enumerate() {
         let mut pink_pos = pos;
         pink_pos.y *= -1.0;
-        lights.push(Box::new(SphereLight::new(
-            pink_pos,
-            rad,
-            pink,
-        )));
-        lights.push(Box::new(SphereLight::new(
-            pos,
-            rad,
-            blue,
-        )));
-        // hitables.push(Sphere::new(
+        lights.push(
+            pink_group,
+            SphereLight::new(
+                animation.track_or(&format!("pink_fill.{}.position", i), pink_pos)?,
+                rad,
+                pink,
+            ),
+        );
+        lights.push(
+            blue_group,
+            SphereLight::new(
+                animation.track_or(&format!("blue_fill.{}.position", i), pos)?,
+                rad,
+                blue,
+            ),
+        );
+        // hitables.push(format!("pink_fill.{}", i), Sphere::new(
         //     pink_pos,
         //     rad - 0.01,
         //     pink_emissive,
         // ));
-        // hitables.push(Sphere::new(
+        // hitables.push(format!("blue_fill.{}", i), Sphere::new(
         //     pos,
         //     rad - 0.01,
         //     blue_emissive,
@@ -118,28 +183,376 @@ fn setup() -> (CameraHandle, World) {
     //     Vec3::new(0.0, 0.8, 0.0),
     //     Vec3::new(0.0, 1.0, 0.0),
     // );
-    let camera = PinholeCamera::new(
-        res,
-        60.0,
-        Vec3::new(1.5, -0.4, 2.0) * 2.25,
-        // Vec3::new(1.3, -0.4, 1.6),
-        Vec3::new(0.0, 0.5, 0.0),
-        Vec3::new(0.0, 1.0, 0.0),
-    );
+    let origin = animation.track_or("camera.origin", Vec3::new(1.5, -0.4, 2.0) * 2.25)?;
+    // Vec3::new(1.3, -0.4, 1.6),
+    let at = animation.track_or("camera.at", Vec3::new(0.0, 0.5, 0.0))?;
+    let up = animation.track_or("camera.up", Vec3::new(0.0, 1.0, 0.0))?;
+
+    let camera: Box<dyn Camera> = match options.projection {
+        Projection::Perspective => match animation.track::<f32>("camera.aperture")? {
+            Some(aperture) => {
+                let focus = animation.track_or("camera.focus", Vec3::new(0.0, 0.5, 0.0))?;
+                let aperture_shape = match (&options.aperture_mask, options.aperture_blades) {
+                    (Some(path), _) => ApertureShape::Mask(ApertureMask::open(path)?),
+                    (None, Some(blades)) => ApertureShape::Polygon {
+                        blades,
+                        rotation: options.aperture_rotation,
+                    },
+                    (None, None) => ApertureShape::Circle,
+                };
+                let camera = ThinLensCamera::new(res, 60.0, aperture, origin, at, up, focus)
+                    .with_aperture_shape(aperture_shape)
+                    .with_anamorphic_squeeze(options.anamorphic_squeeze);
+                match options.autofocus {
+                    Some(screen_pos) => Box::new(camera.with_autofocus(screen_pos)),
+                    None => Box::new(camera),
+                }
+            }
+            None => Box::new(PinholeCamera::new(res, 60.0, origin, at, up)),
+        },
+        Projection::Equirectangular => Box::new(EquirectangularCamera::new(res, origin, at, up)),
+        Projection::Fisheye(fisheye) => {
+            Box::new(FisheyeCamera::new(res, 180.0, fisheye, origin, at, up))
+        }
+        Projection::Realistic => {
+            let lens = match &options.lens {
+                Some(path) => LensPrescription::open(path)?,
+                None => LensPrescription::parse(DOUBLE_GAUSS_50MM)?,
+            };
+            let lens = match options.lens_aperture {
+                Some(diameter) => lens.with_aperture_diameter(diameter),
+                None => lens,
+            };
+            let focus = animation.track_or("camera.focus", Vec3::new(0.0, 0.5, 0.0))?;
+            let focus_distance = (focus.sample_at(0.0) - origin.sample_at(0.0)).mag();
+            // full frame 35mm film
+            Box::new(RealisticCamera::new(
+                res,
+                lens,
+                43.27,
+                focus_distance,
+                origin,
+                at,
+                up,
+            )?)
+        }
+        // 64mm interpupillary distance at a scene scale of roughly 1 unit per meter
+        Projection::Ods(layout) => {
+            Box::new(OdsCamera::new(res, layout, 0.064, 60.0, origin, at, up)?)
+        }
+    };
+
+    // the clip distances apply to every camera
+    let clip = |camera: Box<dyn Camera>| -> Box<dyn Camera> {
+        match (options.near_clip, options.far_clip) {
+            (None, None) => camera,
+            (near, far) => Box::new(ClippedCamera::new(
+                camera,
+                near.unwrap_or(0.0),
+                far.unwrap_or(std::f32::INFINITY),
+            )),
+        }
+    };
 
     let mut cameras = CameraStore::new();
 
-    let camera = cameras.add_camera(Box::new(camera));
+    cameras.add_camera("main", clip(camera));
 
-    (
-        camera,
-        World {
-            materials,
-            hitables,
-            lights,
-            cameras,
-        },
-    )
+    // extra plain perspective cameras, one for each `cameras.<name>.origin` channel in the
+    // animation, aimed by optional `cameras.<name>.at` and `cameras.<name>.up` channels
+    let mut extra_cameras = animation
+        .channel_names()
+        .filter_map(|channel| {
+            let name = channel.strip_prefix("cameras.")?.strip_suffix(".origin")?;
+            Some(String::from(name))
+        })
+        .collect::<Vec<_>>();
+    extra_cameras.sort();
+    for name in extra_cameras {
+        let camera = PinholeCamera::new(
+            res,
+            60.0,
+            animation.track_or(&format!("cameras.{}.origin", name), Vec3::zero())?,
+            animation.track_or(&format!("cameras.{}.at", name), Vec3::new(0.0, 0.5, 0.0))?,
+            animation.track_or(&format!("cameras.{}.up", name), Vec3::new(0.0, 1.0, 0.0))?,
+        );
+        cameras.add_camera(name, clip(Box::new(camera)));
+    }
+
+    Ok(World {
+        materials,
+        hitables,
+        lights,
+        cameras,
+        radius: world_radius,
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Projection {
+    Perspective,
+    Equirectangular,
+    Fisheye(FisheyeProjection),
+    Ods(StereoLayout),
+    Realistic,
+}
+
+struct Options {
+    animation: Option<PathBuf>,
+    // names of the cameras to render, "all" for every camera, or just "main" if empty
+    cameras: Vec<String>,
+    projection: Projection,
+    aperture_blades: Option<usize>,
+    aperture_rotation: f32,
+    aperture_mask: Option<PathBuf>,
+    anamorphic_squeeze: f32,
+    autofocus: Option<Vec2>,
+    lens: Option<PathBuf>,
+    lens_aperture: Option<f32>,
+    shutter_curve: ShutterCurve,
+    rolling_shutter: Option<f32>,
+    near_clip: Option<f32>,
+    far_clip: Option<f32>,
+    filter: AnyFilter,
+    sampler: AnySampler,
+    // scramble pixels with a blue noise mask rather than white noise
+    blue_noise: bool,
+    reconstruction: Reconstruction,
+    // progressive passes rendered per frame, saving images after each, and the number of
+    // wide samples per pixel in each pass
+    passes: usize,
+    pass_samples: usize,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    // save a checkpoint of each film after every pass, and within passes every
+    // `checkpoint_interval` wide samples per pixel so that long passes aren't lost to a crash
+    checkpoint: bool,
+    checkpoint_interval: usize,
+    // continue from the checkpoints saved by an earlier render
+    resume: bool,
+    denoiser: Option<Denoiser>,
+    max_direct_radiance: Option<f32>,
+    max_indirect_radiance: Option<f32>,
+    outlier_rejection: Option<f32>,
+    // write depth normalized between these near and far distances instead of raw distances
+    depth_range: Option<Range<f32>>,
+    // intensities or strengths of each post processing effect, which are off when None
+    bloom: Option<f32>,
+    glare: Option<f32>,
+    chromatic_aberration: Option<f32>,
+    vignette: Option<f32>,
+    film_grain: Option<f32>,
+}
+
+fn next_value<T>(args: &mut impl Iterator<Item = String>, arg: &str) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = args
+        .next()
+        .ok_or_else(|| format!("{} requires a value", arg))?;
+    value
+        .parse()
+        .map_err(|e| format!("Invalid value for {}: {}", arg, e))
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        animation: None,
+        cameras: Vec::new(),
+        projection: Projection::Perspective,
+        aperture_blades: None,
+        aperture_rotation: 0.0,
+        aperture_mask: None,
+        anamorphic_squeeze: 1.0,
+        autofocus: None,
+        lens: None,
+        lens_aperture: None,
+        shutter_curve: ShutterCurve::Box,
+        rolling_shutter: None,
+        near_clip: None,
+        far_clip: None,
+        filter: AnyFilter::BlackmanHarris(BlackmanHarrisFilter::new(1.5)),
+        sampler: AnySampler::default(),
+        blue_noise: false,
+        reconstruction: Reconstruction::ImportanceSampled,
+        passes: 1,
+        pass_samples: SAMPLES,
+        adaptive_sampling: None,
+        checkpoint: false,
+        checkpoint_interval: 16,
+        resume: false,
+        denoiser: None,
+        max_direct_radiance: None,
+        max_indirect_radiance: None,
+        outlier_rejection: None,
+        depth_range: None,
+        bloom: None,
+        glare: None,
+        chromatic_aberration: None,
+        vignette: None,
+        film_grain: None,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--animation" => options.animation = Some(next_value(&mut args, &arg)?),
+            "--aperture-blades" => options.aperture_blades = Some(next_value(&mut args, &arg)?),
+            "--aperture-rotation" => options.aperture_rotation = next_value(&mut args, &arg)?,
+            "--aperture-mask" => options.aperture_mask = Some(next_value(&mut args, &arg)?),
+            "--anamorphic-squeeze" => options.anamorphic_squeeze = next_value(&mut args, &arg)?,
+            "--autofocus" => options.autofocus = Some(Vec2::new(0.5, 0.5)),
+            "--autofocus-at" => {
+                let u = next_value(&mut args, &arg)?;
+                let v = next_value(&mut args, &arg)?;
+                options.autofocus = Some(Vec2::new(u, v));
+            }
+            "--lens" => options.lens = Some(next_value(&mut args, &arg)?),
+            "--lens-aperture" => options.lens_aperture = Some(next_value(&mut args, &arg)?),
+            "--shutter-curve" => {
+                let curve: String = next_value(&mut args, &arg)?;
+                options.shutter_curve = match curve.as_str() {
+                    "box" => ShutterCurve::Box,
+                    "triangle" => ShutterCurve::Triangle,
+                    // trapezoid:<open>,<close>
+                    _ if curve.starts_with("trapezoid:") => {
+                        let mut fractions = curve["trapezoid:".len()..].split(',');
+                        let mut fraction = || -> Result<f32, String> {
+                            fractions
+                                .next()
+                                .and_then(|f| f.parse().ok())
+                                .ok_or_else(|| format!("Invalid shutter curve: {}", curve))
+                        };
+                        ShutterCurve::Trapezoid {
+                            open: fraction()?,
+                            close: fraction()?,
+                        }
+                    }
+                    _ => return Err(format!("Unknown shutter curve: {}", curve)),
+                };
+            }
+            "--rolling-shutter" => options.rolling_shutter = Some(next_value(&mut args, &arg)?),
+            "--near-clip" => options.near_clip = Some(next_value(&mut args, &arg)?),
+            "--far-clip" => options.far_clip = Some(next_value(&mut args, &arg)?),
+            "--camera" => options.cameras.push(next_value(&mut args, &arg)?),
+            "--filter" => {
+                let filter: String = next_value(&mut args, &arg)?;
+                options.filter = AnyFilter::from_name(&filter)?;
+            }
+            "--sampler" => {
+                let sampler: String = next_value(&mut args, &arg)?;
+                options.sampler = AnySampler::from_name(&sampler)?;
+            }
+            "--blue-noise" => options.blue_noise = true,
+            "--passes" => options.passes = next_value(&mut args, &arg)?,
+            "--pass-samples" => options.pass_samples = next_value(&mut args, &arg)?,
+            "--adaptive" => {
+                let threshold = next_value(&mut args, &arg)?;
+                let max_rounds = next_value(&mut args, &arg)?;
+                options.adaptive_sampling = Some(AdaptiveSampling {
+                    threshold,
+                    max_rounds,
+                });
+            }
+            "--checkpoint" => options.checkpoint = true,
+            "--checkpoint-interval" => options.checkpoint_interval = next_value(&mut args, &arg)?,
+            "--resume" => options.resume = true,
+            "--clamp-direct" => options.max_direct_radiance = Some(next_value(&mut args, &arg)?),
+            "--clamp-indirect" => {
+                options.max_indirect_radiance = Some(next_value(&mut args, &arg)?)
+            }
+            "--reject-outliers" => options.outlier_rejection = Some(next_value(&mut args, &arg)?),
+            "--depth-range" => {
+                let near = next_value(&mut args, &arg)?;
+                let far = next_value(&mut args, &arg)?;
+                options.depth_range = Some(near..far);
+            }
+            "--bloom" => options.bloom = Some(next_value(&mut args, &arg)?),
+            "--glare" => options.glare = Some(next_value(&mut args, &arg)?),
+            "--chromatic-aberration" => {
+                options.chromatic_aberration = Some(next_value(&mut args, &arg)?)
+            }
+            "--vignette" => options.vignette = Some(next_value(&mut args, &arg)?),
+            "--film-grain" => options.film_grain = Some(next_value(&mut args, &arg)?),
+            "--denoise" => options.denoiser = Some(Denoiser::default()),
+            "--denoise-iterations" => {
+                options.denoiser = Some(Denoiser {
+                    iterations: next_value(&mut args, &arg)?,
+                    ..Denoiser::default()
+                })
+            }
+            "--reconstruction" => {
+                let reconstruction: String = next_value(&mut args, &arg)?;
+                options.reconstruction = match reconstruction.as_str() {
+                    "importance" => Reconstruction::ImportanceSampled,
+                    "splat" => Reconstruction::Splatted,
+                    _ => return Err(format!("Unknown reconstruction: {}", reconstruction)),
+                };
+            }
+            "--projection" => {
+                let projection: String = next_value(&mut args, &arg)?;
+                options.projection = match projection.as_str() {
+                    "perspective" => Projection::Perspective,
+                    "equirectangular" => Projection::Equirectangular,
+                    "fisheye" => Projection::Fisheye(FisheyeProjection::Equidistant),
+                    "equisolid-fisheye" => Projection::Fisheye(FisheyeProjection::Equisolid),
+                    "ods" => Projection::Ods(StereoLayout::TopBottom),
+                    "ods-left" => Projection::Ods(StereoLayout::Single(StereoEye::Left)),
+                    "ods-right" => Projection::Ods(StereoLayout::Single(StereoEye::Right)),
+                    "realistic" => Projection::Realistic,
+                    _ => return Err(format!("Unknown projection: {}", projection)),
+                };
+            }
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+
+    if let Some(blades) = options.aperture_blades {
+        if blades < 3 {
+            return Err(String::from("--aperture-blades must be at least 3"));
+        }
+    }
+    if options.anamorphic_squeeze <= 0.0 {
+        return Err(String::from("--anamorphic-squeeze must be greater than 0"));
+    }
+
+    if let Some(range) = &options.depth_range {
+        if !(range.start.is_finite() && range.end.is_finite() && range.start < range.end) {
+            return Err(String::from(
+                "--depth-range requires finite near and far distances with near < far",
+            ));
+        }
+    }
+
+    if let Some(strength) = options.chromatic_aberration {
+        if !(0.0..1.0).contains(&strength) {
+            return Err(String::from(
+                "--chromatic-aberration must be at least 0 and less than 1",
+            ));
+        }
+    }
+    // the Owen and Halton scrambled samplers only use the scramble as a seed, which throws
+    // away the mask's spatial ordering
+    if options.blue_noise && options.sampler.pixel_randomization() != PixelRandomization::Rotation {
+        return Err(format!(
+            "--blue-noise requires a rotated sampler (rd or random), not {}",
+            options.sampler.name()
+        ));
+    }
+    if options.checkpoint_interval == 0 {
+        return Err(String::from("--checkpoint-interval must be at least 1"));
+    }
+
+    if options.filter.has_negative_lobes()
+        && options.reconstruction == Reconstruction::ImportanceSampled
+    {
+        return Err(String::from(
+            "Filters with negative lobes require --reconstruction splat",
+        ));
+    }
+
+    Ok(options)
 }
 
 fn main() {
@@ -148,61 +561,275 @@ fn main() {
         .build_global()
         .unwrap();
 
-    let (camera, world) = setup();
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let animation = match &options.animation {
+        Some(path) => match Animation::open(path) {
+            Ok(animation) => animation,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => Animation::new(),
+    };
+
+    let mut world = match setup(&animation, &options) {
+        Ok(world) => world,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let cameras: Vec<CameraHandle> = if options.cameras.is_empty() {
+        vec![world.cameras.find("main").unwrap()]
+    } else if options.cameras.iter().any(|name| name == "all") {
+        world.cameras.handles().collect()
+    } else {
+        options
+            .cameras
+            .iter()
+            .map(|name| {
+                world.cameras.find(name).unwrap_or_else(|| {
+                    eprintln!("Unknown camera: {}", name);
+                    std::process::exit(1);
+                })
+            })
+            .collect()
+    };
+
+    // generated once and shared, since it's slow to make
+    let blue_noise = if options.blue_noise {
+        println!("Generating blue noise...");
+        Some(BlueNoiseMask::new(64, 0))
+    } else {
+        None
+    };
+
+    // one LightGroup channel for each of the world's light groups
+    let mut channels = vec![
+        ChannelKind::Color,
+        ChannelKind::Alpha,
+        ChannelKind::Background,
+        ChannelKind::WorldNormal,
+        ChannelKind::Depth,
+        ChannelKind::WorldPosition,
+        ChannelKind::MotionVector,
+        ChannelKind::Albedo,
+        ChannelKind::MaterialId,
+        ChannelKind::ObjectId,
+        ChannelKind::CryptoObject,
+        ChannelKind::CryptoMaterial,
+    ];
+    channels.extend((0..world.lights.group_names().len()).map(ChannelKind::LightGroup));
+    channels.extend_from_slice(&[
+        ChannelKind::Direct,
+        ChannelKind::Indirect,
+        ChannelKind::Diffuse,
+        ChannelKind::Specular,
+        ChannelKind::FilterWeight,
+        ChannelKind::SampleCount,
+    ]);
+    // everything but the background and filter weights is saved, with color last
+    let outputs = channels
+        .iter()
+        .copied()
+        .filter(|kind| {
+            !matches!(
+                kind,
+                ChannelKind::Color | ChannelKind::Background | ChannelKind::FilterWeight
+            )
+        })
+        .chain(std::iter::once(ChannelKind::Color))
+        .collect::<Vec<_>>();
 
-    let mut film = Film::<U4>::new(
-        &[
-            ChannelKind::Color,
-            ChannelKind::Alpha,
-            ChannelKind::Background,
-            ChannelKind::WorldNormal,
-        ],
-        Extent2u::new(RES.0, RES.1),
-    )
-    .unwrap();
+    // one film per camera, saved to renders/ or, past the main camera, a folder named after it
+    let mut films = cameras
+        .into_iter()
+        .map(|camera| {
+            let mut film = Film::new(&channels, Extent2u::new(RES.0, RES.1)).unwrap();
+            film.set_reconstruction(options.reconstruction).unwrap();
+            film.set_adaptive_sampling(options.adaptive_sampling);
+            film.set_sampler(options.sampler);
+            film.set_blue_noise(blue_noise.clone());
+            film.set_denoiser(options.denoiser);
+            film.set_outlier_rejection(options.outlier_rejection);
+            film.set_depth_range(options.depth_range.clone());
+            if let Some(intensity) = options.bloom {
+                film.add_post_effect(Bloom::new(intensity));
+            }
+            if let Some(intensity) = options.glare {
+                // streaks follow the aperture's blades, or a common six bladed iris
+                let blades = options.aperture_blades.unwrap_or(6);
+                film.add_post_effect(Glare::new(intensity, blades, options.aperture_rotation));
+            }
+            if let Some(strength) = options.chromatic_aberration {
+                film.add_post_effect(ChromaticAberration { strength });
+            }
+            if let Some(strength) = options.vignette {
+                film.add_post_effect(Vignette { strength });
+            }
+            if let Some(strength) = options.film_grain {
+                film.add_post_effect(FilmGrain { strength, seed: 0 });
+            }
+            film.set_cryptomatte_names(ChannelKind::CryptoObject, world.hitables.names());
+            film.set_cryptomatte_names(ChannelKind::CryptoMaterial, world.materials.names());
+            film.set_light_group_names(world.lights.group_names());
+            let output_folder = match world.cameras.name(camera) {
+                "main" => PathBuf::from("renders"),
+                name => PathBuf::from("renders").join(name),
+            };
+            (camera, output_folder, film)
+        })
+        .collect::<Vec<_>>();
 
     let frame_rate = 24;
     let frame_range = 1..2;
     let shutter_speed = 1.0 / 24.0;
 
-    let filter = BlackmanHarrisFilter::new(1.5);
+    let filter = options.filter;
     // let filter = BoxFilter::default();
-    let integrator = PathTracingIntegrator { max_bounces: 5 };
+    let integrator = PathTracingIntegrator {
+        max_bounces: 5,
+        split_light_paths: true,
+        max_direct_radiance: options.max_direct_radiance,
+        max_indirect_radiance: options.max_indirect_radiance,
+    };
 
     for frame in frame_range {
-        let start = Instant::now();
-
         let frame_start = frame as f32 * (1.0 / frame_rate as f32);
-        let frame_end = frame_start + shutter_speed;
-
-        film.render_frame_into(
-            &world,
-            camera,
-            &integrator,
-            &filter,
-            Extent2u::new(16, 16),
-            frame,
-            frame_start..frame_end,
-            SAMPLES,
-        );
+        let shutter = Shutter::new(frame_start, shutter_speed).with_curve(options.shutter_curve);
+        let shutter = match options.rolling_shutter {
+            Some(readout_time) => shutter.with_rolling_shutter(readout_time),
+            None => shutter,
+        };
 
-        let time = Instant::now() - start;
-        let time_secs = time.as_secs();
-        let time_millis = time.subsec_millis();
+        world.prepare_frame(shutter.time_range());
 
-        println!(
-            "Done in {} seconds.",
-            time_secs as f32 + time_millis as f32 / 1000.0
-        );
+        for (camera, output_folder, film) in films.iter_mut() {
+            let checkpoint_path = output_folder.join("checkpoint.bin");
 
-        println!("Post processing image...");
+            // a checkpoint from a later frame means this one was already finished
+            let resumed_frame = if options.resume && checkpoint_path.exists() {
+                match film.load_checkpoint(&checkpoint_path) {
+                    Ok(frame) => Some(frame),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                None
+            };
+            // the pass to resume from, and how many of its wide samples were already taken
+            let (first_pass, mut pass_samples_taken) = match resumed_frame {
+                Some(resumed_frame) if resumed_frame > frame => continue,
+                Some(resumed_frame) if resumed_frame == frame => {
+                    let wide_samples = film.samples_taken() / 4;
+                    println!(
+                        "Resuming camera {} from {} samples per pixel...",
+                        world.cameras.name(*camera),
+                        film.samples_taken()
+                    );
+                    (
+                        wide_samples / options.pass_samples,
+                        wide_samples % options.pass_samples,
+                    )
+                }
+                _ => {
+                    film.clear();
+                    (0, 0)
+                }
+            };
 
-        film.save_to(
-            &[ChannelKind::Alpha, ChannelKind::WorldNormal, ChannelKind::Color],
-            "renders",
-            format!("{}_spp", SAMPLES * 4),
-            true,
-        )
-        .unwrap();
+            for pass in first_pass..options.passes {
+                println!(
+                    "Rendering camera {}, pass {} of {}...",
+                    world.cameras.name(*camera),
+                    pass + 1,
+                    options.passes
+                );
+
+                let start = Instant::now();
+
+                // with checkpoints on, render the pass in pieces and checkpoint after each
+                while pass_samples_taken < options.pass_samples {
+                    let remaining = options.pass_samples - pass_samples_taken;
+                    let samples = if options.checkpoint {
+                        remaining.min(options.checkpoint_interval)
+                    } else {
+                        remaining
+                    };
+
+                    film.render_frame_into(
+                        &world,
+                        *camera,
+                        &integrator,
+                        &filter,
+                        Extent2u::new(16, 16),
+                        frame,
+                        &shutter,
+                        samples,
+                    );
+                    pass_samples_taken += samples;
+
+                    // the end of the pass is checkpointed once its images are saved
+                    if options.checkpoint && pass_samples_taken < options.pass_samples {
+                        if let Err(e) = film.save_checkpoint(&checkpoint_path, frame) {
+                            eprintln!("{}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                pass_samples_taken = 0;
+
+                // after the whole pass, so checkpointing doesn't change which pixels get more
+                film.render_adaptive_rounds_into(
+                    &world,
+                    *camera,
+                    &integrator,
+                    &filter,
+                    Extent2u::new(16, 16),
+                    frame,
+                    &shutter,
+                    options.pass_samples,
+                );
+
+                let time = Instant::now() - start;
+                let time_secs = time.as_secs();
+                let time_millis = time.subsec_millis();
+
+                println!(
+                    "Done in {} seconds.",
+                    time_secs as f32 + time_millis as f32 / 1000.0
+                );
+
+                println!("Post processing image...");
+
+                if let Err(e) = film.save_to(
+                    &outputs,
+                    output_folder.as_path(),
+                    format!("{:04}_{}_spp", frame, film.samples_taken()),
+                    true,
+                ) {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+
+                if options.checkpoint {
+                    if let Err(e) = film.save_checkpoint(&checkpoint_path, frame) {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
     }
 }
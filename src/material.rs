@@ -3,6 +3,7 @@ use bumpalo::Bump;
 use arrayref::array_ref;
 
 use crate::hitable::WShadingPoint;
+use crate::light::LightGroup;
 use crate::math::{f32x4, f_schlick, OrthonormalBasis, RandomSample3d, Wec3};
 use crate::spectrum::{Srgb, WSrgb};
 
@@ -23,9 +24,19 @@ pub trait BSDF {
 
     fn f(&self, wo: Wec3, wi: Wec3, n: Wec3) -> WSrgb;
 
+    /// `f` split into its (diffuse, specular) lobes.
+    fn f_lobes(&self, wo: Wec3, wi: Wec3, n: Wec3) -> (WSrgb, WSrgb) {
+        (self.f(wo, wi, n), WSrgb::zero())
+    }
+
     fn le(&self, _wo: Wec3, _intersection: &WShadingPoint) -> WSrgb {
         WSrgb::zero()
     }
+
+    /// The overall reflectance of the surface, as used for denoiser feature buffers.
+    fn albedo(&self) -> WSrgb {
+        WSrgb::zero()
+    }
 }
 
 pub trait Material: Send + Sync {
@@ -41,25 +52,58 @@ pub struct WScatteringEvent {
     pub wi: Wec3,
     pub f: WSrgb,
     pub pdf: f32x4,
+    /// lanes which scattered off a specular lobe rather than a diffuse one
+    pub specular: f32x4,
 }
 
 #[derive(Clone, Copy, Debug)]
 pub struct MaterialHandle(pub usize);
 
-pub struct MaterialStore(Vec<Box<dyn Material>>);
+pub struct MaterialStore {
+    materials: Vec<Box<dyn Material>>,
+    names: Vec<String>,
+    // light group that emission from each material is rendered into
+    light_groups: Vec<Option<LightGroup>>,
+}
 
 impl MaterialStore {
     pub fn new() -> Self {
-        MaterialStore(Vec::new())
+        MaterialStore {
+            materials: Vec::new(),
+            names: Vec::new(),
+            light_groups: Vec::new(),
+        }
+    }
+
+    pub fn add_material<S: Into<String>, M: Material + 'static>(
+        &mut self,
+        name: S,
+        material: M,
+    ) -> MaterialHandle {
+        self.materials.push(Box::new(material));
+        self.names.push(name.into());
+        self.light_groups.push(None);
+        MaterialHandle(self.materials.len() - 1)
     }
 
-    pub fn add_material<M: Material + 'static>(&mut self, material: M) -> MaterialHandle {
-        self.0.push(Box::new(material));
-        MaterialHandle(self.0.len() - 1)
+    pub fn set_light_group(&mut self, handle: MaterialHandle, group: LightGroup) {
+        self.light_groups[handle.0] = Some(group);
+    }
+
+    pub fn light_group(&self, handle: MaterialHandle) -> Option<LightGroup> {
+        self.light_groups[handle.0]
     }
 
     pub fn get(&self, handle: MaterialHandle) -> &dyn Material {
-        self.0[handle.0].as_ref()
+        self.materials[handle.0].as_ref()
+    }
+
+    pub fn name(&self, handle: MaterialHandle) -> &str {
+        &self.names[handle.0]
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
     }
 }
 
@@ -124,12 +168,17 @@ impl BSDF for LambertianBSDF {
             wi: diffuse_bounce,
             f: diffuse_f,
             pdf: diffuse_pdf,
+            specular: f32x4::ZERO,
         })
     }
 
     fn f(&self, _wi: Wec3, _wo: Wec3, _n: Wec3) -> WSrgb {
         self.albedo / f32x4::PI
     }
+
+    fn albedo(&self) -> WSrgb {
+        self.albedo
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -184,6 +233,11 @@ where
 
 impl BSDF for DielectricBSDF {
     fn f(&self, wi: Wec3, wo: Wec3, n: Wec3) -> WSrgb {
+        let (diffuse_f, spec_f) = self.f_lobes(wi, wo, n);
+        spec_f + diffuse_f
+    }
+
+    fn f_lobes(&self, wi: Wec3, wo: Wec3, n: Wec3) -> (WSrgb, WSrgb) {
         let dot = f32x4::ZERO.max(wi.dot(n));
         let fresnel = f_schlick(dot, f32x4::from(0.04));
         let half = (wo + wi).normalized();
@@ -192,7 +246,11 @@ impl BSDF for DielectricBSDF {
         let spec_factor = cos_alpha * (self.roughness + two) / (two * f32x4::PI);
         let spec_f = WSrgb::one() * spec_factor * fresnel;
         let diffuse_f = self.albedo / f32x4::PI * (f32x4::ONE - fresnel);
-        spec_f + diffuse_f
+        (diffuse_f, spec_f)
+    }
+
+    fn albedo(&self) -> WSrgb {
+        self.albedo
     }
 
     fn scatter(
@@ -243,6 +301,7 @@ impl BSDF for DielectricBSDF {
             wi: Wec3::merge(fresnel_mask, spec_bounce, diffuse_bounce),
             f: WSrgb::merge(fresnel_mask, spec_f, diffuse_f),
             pdf: fresnel * spec_pdf + (f32x4::ONE - fresnel) * diffuse_pdf,
+            specular: fresnel_mask,
         })
     }
 }
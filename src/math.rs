@@ -112,6 +112,13 @@ pub fn saturate(v: f32x4) -> f32x4 {
     v.min(f32x4::ONE).max(f32x4::ZERO)
 }
 
+/// Apply a scalar function to each lane, for operations which have no wide implementation.
+#[inline]
+pub fn map_lanes<F: Fn(f32) -> f32>(v: f32x4, f: F) -> f32x4 {
+    let v = v.as_ref();
+    f32x4::from([f(v[0]), f(v[1]), f(v[2]), f(v[3])])
+}
+
 pub struct CDF {
     items: Vec<(f32, f32)>,
     densities: Vec<f32>,
@@ -169,6 +176,106 @@ impl CDF {
     }
 }
 
+/// A piecewise-constant 1D distribution which is sampled continuously in proportion to
+/// its function values.
+#[derive(Clone)]
+pub struct Distribution1D {
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// An empty `func` is treated as a single zero segment, which samples uniformly.
+    pub fn new(func: &[f32]) -> Self {
+        let func = if func.is_empty() { &[0.0][..] } else { func };
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.0) / n as f32;
+        }
+
+        let integral = cdf[n];
+        if integral > 0.0 {
+            for c in cdf.iter_mut().skip(1) {
+                *c /= integral;
+            }
+        } else {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n as f32;
+            }
+        }
+
+        Distribution1D { cdf, integral }
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Takes a uniform sample on [0, 1) and returns a sample on [0, 1) distributed
+    /// according to the function, along with the index of the segment it lies in.
+    pub fn sample(&self, u: f32) -> (f32, usize) {
+        let n = self.cdf.len() - 1;
+
+        // find the last segment whose cdf starts at or below u
+        let mut lo = 0;
+        let mut hi = n;
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if self.cdf[mid] <= u {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+
+        let width = self.cdf[lo + 1] - self.cdf[lo];
+        let du = if width > 0.0 {
+            ((u - self.cdf[lo]) / width).min(1.0).max(0.0)
+        } else {
+            0.0
+        };
+
+        ((lo as f32 + du) / n as f32, lo)
+    }
+}
+
+/// A piecewise-constant 2D distribution over a `width` by `height` grid of function values
+/// stored in rows.
+#[derive(Clone)]
+pub struct Distribution2D {
+    conditionals: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        let conditionals = func
+            .chunks_exact(width)
+            .take(height)
+            .map(Distribution1D::new)
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(
+            &conditionals
+                .iter()
+                .map(|row| row.integral())
+                .collect::<Vec<_>>(),
+        );
+        Distribution2D {
+            conditionals,
+            marginal,
+        }
+    }
+
+    /// Takes a uniform sample on [0, 1)^2 and returns a sample on [0, 1)^2 distributed
+    /// according to the function.
+    pub fn sample(&self, u: Vec2) -> Vec2 {
+        let (y, row) = self.marginal.sample(u.y);
+        let (x, _) = self.conditionals[row].sample(u.x);
+        Vec2::new(x, y)
+    }
+}
+
 #[inline]
 #[allow(dead_code)]
 pub fn power_heuristic(n_samples_f: usize, f_pdf: f32, n_samples_g: usize, g_pdf: f32) -> f32 {
@@ -0,0 +1,337 @@
+//! Lens and film effects applied to the linear HDR color before it is tone mapped.
+
+use rand::prelude::*;
+
+use crate::math::{Extent2u, Vec2};
+use crate::spectrum::Srgb;
+
+pub trait PostEffect: Send + Sync {
+    /// Apply the effect in place to `image`, stored bottom row first like the film's channels,
+    /// for the animation's `frame`.
+    fn apply(&self, image: &mut [Srgb], res: Extent2u, frame: usize);
+}
+
+/// Glow around bright areas from light scattered inside the lens, made by convolving the
+/// parts of the image brighter than `threshold` with a kernel with a long tail.
+#[derive(Clone, Copy, Debug)]
+pub struct Bloom {
+    pub intensity: f32,
+    /// Spread of the narrowest part of the kernel, as a fraction of the image width.
+    pub radius: f32,
+    pub threshold: f32,
+}
+
+impl Bloom {
+    pub fn new(intensity: f32) -> Self {
+        Bloom {
+            intensity,
+            radius: 0.005,
+            threshold: 1.0,
+        }
+    }
+}
+
+// gaussians of doubling width summed with halving weights approximate a kernel that falls off
+// slowly, like real lens scattering does
+const BLOOM_OCTAVES: usize = 4;
+
+impl PostEffect for Bloom {
+    fn apply(&self, image: &mut [Srgb], res: Extent2u, _frame: usize) {
+        let bright = bright_parts(image, self.threshold);
+
+        let mut bloom = vec![Srgb::zero(); image.len()];
+        let mut total_weight = 0.0;
+        for octave in 0..BLOOM_OCTAVES {
+            let sigma = self.radius * res.w as f32 * (1 << octave) as f32;
+            let weight = 0.5f32.powi(octave as i32);
+            for (sum, blurred) in bloom.iter_mut().zip(gaussian_blur(&bright, res, sigma)) {
+                *sum += blurred * weight;
+            }
+            total_weight += weight;
+        }
+
+        for (pixel, bloom) in image.iter_mut().zip(bloom) {
+            *pixel += bloom * (self.intensity / total_weight);
+        }
+    }
+}
+
+/// Star shaped streaks from diffraction around the aperture blades. Each edge of the polygonal
+/// aperture spreads light perpendicular to itself, so an even number of blades gives one spike
+/// per blade and an odd number gives two.
+#[derive(Clone, Copy, Debug)]
+pub struct Glare {
+    pub intensity: f32,
+    pub blades: usize,
+    /// In degrees, matching the aperture's rotation.
+    pub rotation: f32,
+    /// Length of each streak, as a fraction of the image width.
+    pub length: f32,
+    pub threshold: f32,
+}
+
+impl Glare {
+    pub fn new(intensity: f32, blades: usize, rotation: f32) -> Self {
+        Glare {
+            intensity,
+            blades,
+            rotation,
+            length: 0.1,
+            threshold: 1.0,
+        }
+    }
+
+    /// The direction of each streak, in radians. Streaks run along the normals of the
+    /// aperture's edges, which sit halfway between its vertices. With an odd number of blades
+    /// the opposite of each normal points at a vertex, so together they fall on every
+    /// multiple of `pi / blades` from the rotation.
+    fn streak_angles(&self) -> Vec<f32> {
+        let (streaks, offset) = if self.blades % 2 == 0 {
+            (self.blades, 0.5)
+        } else {
+            (self.blades * 2, 0.0)
+        };
+        (0..streaks)
+            .map(|i| {
+                self.rotation.to_radians()
+                    + std::f32::consts::PI * 2.0 * (i as f32 + offset) / streaks as f32
+            })
+            .collect()
+    }
+}
+
+impl PostEffect for Glare {
+    fn apply(&self, image: &mut [Srgb], res: Extent2u, _frame: usize) {
+        let bright = bright_parts(image, self.threshold);
+
+        let directions = self
+            .streak_angles()
+            .iter()
+            .map(|angle| Vec2::new(angle.cos(), angle.sin()))
+            .collect::<Vec<_>>();
+        let streaks = directions.len();
+
+        let length = (self.length * res.w as f32).max(1.0) as usize;
+        // each streak fades to about 1% of its start by its end
+        let falloff = 0.01f32.powf(1.0 / length as f32);
+        let weight = self.intensity * (1.0 - falloff) / streaks as f32;
+
+        for y in 0..res.h {
+            for x in 0..res.w {
+                let source = bright[x + y * res.w];
+                if source.max_channel() <= 0.0 {
+                    continue;
+                }
+                for dir in directions.iter() {
+                    let mut energy = weight;
+                    for step in 1..=length {
+                        energy *= falloff;
+                        let px = (x as f32 + 0.5 + dir.x * step as f32).floor();
+                        let py = (y as f32 + 0.5 + dir.y * step as f32).floor();
+                        if px < 0.0 || py < 0.0 || px >= res.w as f32 || py >= res.h as f32 {
+                            break;
+                        }
+                        image[px as usize + py as usize * res.w] += source * energy;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Darkening towards the corners, falling off like `1 - strength * r^2` where `r` is the
+/// distance from the center relative to the distance to the corners.
+#[derive(Clone, Copy, Debug)]
+pub struct Vignette {
+    pub strength: f32,
+}
+
+impl PostEffect for Vignette {
+    fn apply(&self, image: &mut [Srgb], res: Extent2u, _frame: usize) {
+        let center = Vec2::new(res.w as f32, res.h as f32) * 0.5;
+        let corner_dist_sq = center.mag_sq();
+
+        for y in 0..res.h {
+            for x in 0..res.w {
+                let offset = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - center;
+                let r_sq = offset.mag_sq() / corner_dist_sq;
+                image[x + y * res.w] *= (1.0 - self.strength * r_sq).max(0.0);
+            }
+        }
+    }
+}
+
+/// Lateral chromatic aberration: red is magnified and blue shrunk about the center of the
+/// image by `strength`, fringing edges towards the corners. `strength` must be at least 0
+/// and less than 1.
+#[derive(Clone, Copy, Debug)]
+pub struct ChromaticAberration {
+    pub strength: f32,
+}
+
+impl PostEffect for ChromaticAberration {
+    fn apply(&self, image: &mut [Srgb], res: Extent2u, _frame: usize) {
+        let source = image.to_vec();
+        let center = Vec2::new(res.w as f32, res.h as f32) * 0.5;
+
+        for y in 0..res.h {
+            for x in 0..res.w {
+                let offset = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - center;
+                // a magnified channel shows what is nearer the center at each pixel
+                let red = sample_bilinear(&source, res, center + offset / (1.0 + self.strength));
+                let blue = sample_bilinear(&source, res, center + offset / (1.0 - self.strength));
+                let pixel = &mut image[x + y * res.w];
+                *pixel = Srgb::new(red.x, pixel.y, blue.z);
+            }
+        }
+    }
+}
+
+/// Monochrome noise scaling each pixel by up to `strength` either way, strongest in the
+/// midtones like the grain of real film. The grain is different every frame, mixed from
+/// `seed` and the frame number.
+#[derive(Clone, Copy, Debug)]
+pub struct FilmGrain {
+    pub strength: f32,
+    pub seed: u64,
+}
+
+impl PostEffect for FilmGrain {
+    fn apply(&self, image: &mut [Srgb], _res: Extent2u, frame: usize) {
+        let seed = self.seed ^ (frame as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let mut rng = SmallRng::seed_from_u64(seed);
+
+        for pixel in image.iter_mut() {
+            // triangular noise in [-1, 1] looks more natural than uniform noise
+            let noise = rng.gen::<f32>() - rng.gen::<f32>();
+            let lum = pixel.luminance().max(0.0).min(1.0);
+            let midtones = 4.0 * lum * (1.0 - lum);
+            *pixel *= 1.0 + noise * self.strength * midtones.max(0.25);
+        }
+    }
+}
+
+/// The amount each pixel exceeds `threshold` by, keeping its color.
+fn bright_parts(image: &[Srgb], threshold: f32) -> Vec<Srgb> {
+    image
+        .iter()
+        .map(|pixel| {
+            let brightest = pixel.max_channel();
+            if brightest > threshold {
+                *pixel * ((brightest - threshold) / brightest)
+            } else {
+                Srgb::zero()
+            }
+        })
+        .collect()
+}
+
+fn gaussian_blur(image: &[Srgb], res: Extent2u, sigma: f32) -> Vec<Srgb> {
+    let radius = (sigma * 3.0).ceil().max(1.0) as isize;
+    let kernel = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect::<Vec<_>>();
+
+    // the kernel is separable, so blur the rows and then the columns
+    let mut rows = vec![Srgb::zero(); image.len()];
+    for y in 0..res.h {
+        for x in 0..res.w {
+            let mut sum = Srgb::zero();
+            let mut weight_sum = 0.0;
+            for (i, weight) in kernel.iter().enumerate() {
+                let sx = x as isize + i as isize - radius;
+                if sx >= 0 && sx < res.w as isize {
+                    sum += image[sx as usize + y * res.w] * *weight;
+                    weight_sum += weight;
+                }
+            }
+            rows[x + y * res.w] = sum / weight_sum;
+        }
+    }
+
+    let mut blurred = vec![Srgb::zero(); image.len()];
+    for y in 0..res.h {
+        for x in 0..res.w {
+            let mut sum = Srgb::zero();
+            let mut weight_sum = 0.0;
+            for (i, weight) in kernel.iter().enumerate() {
+                let sy = y as isize + i as isize - radius;
+                if sy >= 0 && sy < res.h as isize {
+                    sum += rows[x + sy as usize * res.w] * *weight;
+                    weight_sum += weight;
+                }
+            }
+            blurred[x + y * res.w] = sum / weight_sum;
+        }
+    }
+
+    blurred
+}
+
+fn sample_bilinear(image: &[Srgb], res: Extent2u, pos: Vec2) -> Srgb {
+    let x = (pos.x - 0.5).max(0.0).min(res.w as f32 - 1.0);
+    let y = (pos.y - 0.5).max(0.0).min(res.h as f32 - 1.0);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(res.w - 1), (y0 + 1).min(res.h - 1));
+    let (tx, ty) = (x.fract(), y.fract());
+
+    let top = image[x0 + y0 * res.w] * (1.0 - tx) + image[x1 + y0 * res.w] * tx;
+    let bottom = image[x0 + y1 * res.w] * (1.0 - tx) + image[x1 + y1 * res.w] * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::ApertureShape;
+    use crate::math::f32x4;
+
+    use std::f32::consts::PI;
+
+    /// The angles of a polygonal aperture's vertices, found by sampling the outer corner of
+    /// each of its triangles.
+    fn vertex_angles(blades: usize, rotation: f32) -> Vec<f32> {
+        let shape = ApertureShape::Polygon { blades, rotation };
+        (0..blades)
+            .map(|j| {
+                let s0 = (j as f32 + 1.0) / blades as f32 - 1e-5;
+                let point = shape.sample(&[f32x4::from(s0), f32x4::from(0.0)]);
+                let points: [Vec2; 4] = point.into();
+                points[0].y.atan2(points[0].x)
+            })
+            .collect()
+    }
+
+    fn angle_between(a: f32, b: f32) -> f32 {
+        let d = (a - b).rem_euclid(PI * 2.0);
+        d.min(PI * 2.0 - d)
+    }
+
+    #[test]
+    fn glare_streaks_follow_edge_normals() {
+        for blades in 3..=9 {
+            let rotation = 10.0;
+            let vertices = vertex_angles(blades, rotation);
+            // the normal of each edge and its opposite
+            let mut expected = Vec::new();
+            for j in 0..blades {
+                let normal = vertices[j] + PI / blades as f32;
+                expected.push(normal);
+                if blades % 2 == 1 {
+                    expected.push(normal + PI);
+                }
+            }
+
+            let streaks = Glare::new(1.0, blades, rotation).streak_angles();
+            assert_eq!(streaks.len(), expected.len(), "{} blades", blades);
+            for angle in expected {
+                assert!(
+                    streaks.iter().any(|s| angle_between(*s, angle) < 1e-3),
+                    "{} blades: no streak at {} degrees",
+                    blades,
+                    angle.to_degrees()
+                );
+            }
+        }
+    }
+}
@@ -1,6 +1,23 @@
-use crate::math::{f32x4, Vec2u, Vec3, Wec3};
+use crate::math::{f32x4, Vec2, Vec2u, Vec3, Wec3};
 use crate::spectrum::{Srgb, WSrgb};
 
+/// Where on the film a camera sample was taken: the pixel within its tile, and the offset of
+/// the sample from the center of that pixel, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleCoord {
+    pub pixel: Vec2u,
+    pub offset: Vec2,
+}
+
+impl SampleCoord {
+    pub fn new(pixel: Vec2u) -> Self {
+        SampleCoord {
+            pixel,
+            offset: Vec2::zero(),
+        }
+    }
+}
+
 macro_rules! rays {
     ($($n:ident => $t:ident, $st:ident, $tt:ident, $tc:ty, $bt:ty, $scramt:ty, $samplet:ty),+) => {
         $(#[derive(Clone, Copy, Debug)]
@@ -12,6 +29,8 @@ macro_rules! rays {
             pub throughput: $st,
             pub tile_coord: $tc,
             pub valid: $bt,
+            /// whether the path first scattered off a specular lobe
+            pub specular: $bt,
             pub scramble: $scramt,
             pub sample: $samplet,
         }
@@ -26,7 +45,7 @@ macro_rules! rays {
     }
 }
 
-rays!(Ray => Vec3, Srgb, f32, Vec2u, bool, f32, usize, WRay => Wec3, WSrgb, f32x4, [Vec2u; 4], [bool; 4], [f32; 4], [usize; 4]);
+rays!(Ray => Vec3, Srgb, f32, SampleCoord, bool, f32, usize, WRay => Wec3, WSrgb, f32x4, [SampleCoord; 4], [bool; 4], [f32; 4], [usize; 4]);
 
 impl Ray {
     #[allow(dead_code)]
@@ -44,8 +63,9 @@ impl Ray {
             dir,
             radiance: Srgb::zero(),
             throughput: Srgb::one(),
-            tile_coord,
+            tile_coord: SampleCoord::new(tile_coord),
             valid: true,
+            specular: false,
             scramble,
             sample,
         }
@@ -58,8 +78,9 @@ impl Ray {
             dir: Vec3::broadcast(std::f32::NAN),
             radiance: Srgb::zero(),
             throughput: Srgb::zero(),
-            tile_coord: Vec2u::zero(),
+            tile_coord: SampleCoord::new(Vec2u::zero()),
             valid: false,
+            specular: false,
             scramble: 0f32,
             sample: 0,
         }
@@ -82,8 +103,14 @@ impl WRay {
             dir,
             radiance: WSrgb::zero(),
             throughput: WSrgb::one(),
-            tile_coord,
+            tile_coord: [
+                SampleCoord::new(tile_coord[0]),
+                SampleCoord::new(tile_coord[1]),
+                SampleCoord::new(tile_coord[2]),
+                SampleCoord::new(tile_coord[3]),
+            ],
             valid,
+            specular: [false; 4],
             scramble,
             sample,
         }
@@ -141,6 +168,12 @@ impl From<[Ray; 4]> for WRay {
                 rays[3].tile_coord,
             ],
             valid: [rays[0].valid, rays[1].valid, rays[2].valid, rays[3].valid],
+            specular: [
+                rays[0].specular,
+                rays[1].specular,
+                rays[2].specular,
+                rays[3].specular,
+            ],
             scramble: [
                 rays[0].scramble,
                 rays[1].scramble,
@@ -173,6 +206,7 @@ impl Into<[Ray; 4]> for WRay {
                 throughput: throughputs[0],
                 tile_coord: self.tile_coord[0],
                 valid: self.valid[0],
+                specular: self.specular[0],
                 scramble: self.scramble[0],
                 sample: self.sample[0],
             },
@@ -184,6 +218,7 @@ impl Into<[Ray; 4]> for WRay {
                 throughput: throughputs[1],
                 tile_coord: self.tile_coord[1],
                 valid: self.valid[1],
+                specular: self.specular[1],
                 scramble: self.scramble[1],
                 sample: self.sample[1],
             },
@@ -195,6 +230,7 @@ impl Into<[Ray; 4]> for WRay {
                 throughput: throughputs[2],
                 tile_coord: self.tile_coord[2],
                 valid: self.valid[2],
+                specular: self.specular[2],
                 scramble: self.scramble[2],
                 sample: self.sample[2],
             },
@@ -206,6 +242,7 @@ impl Into<[Ray; 4]> for WRay {
                 throughput: throughputs[3],
                 tile_coord: self.tile_coord[3],
                 valid: self.valid[3],
+                specular: self.specular[3],
                 scramble: self.scramble[3],
                 sample: self.sample[3],
             },
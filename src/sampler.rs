@@ -2,65 +2,577 @@ use rand::prelude::*;
 
 use ultraviolet::f32x4;
 
-// pub trait Sampler {
-//     fn begin_pixel(&mut self, pixel: Vec2u);
-//     fn request_samples_2d(&mut self, samples: usize);
-//     fn request_samples_1d(&mut self, samples: usize);
-// }
+/// The largest f32 below one.
+const ONE_MINUS_EPSILON: f32 = 1.0 - std::f32::EPSILON / 2.0;
+
+/// A sequence of sample points, used to fill the tables in `Samples`.
+pub trait Sampler: Send + Sync {
+    /// Fill `buf` with the points `first_sample..` of a `dims` dimensional sequence, with the
+    /// dimensions of each point next to each other. `dim` is the index of the first of those
+    /// dimensions among all of those a camera sample uses, and `seed` decorrelates the
+    /// sequences of different sets of dimensions and different frames.
+    fn fill(&self, dims: usize, dim: usize, seed: u64, first_sample: u64, buf: &mut [f32]);
+
+    /// How the points are made different for each pixel.
+    fn pixel_randomization(&self) -> PixelRandomization;
+}
+
+/// How each pixel randomizes the points it looks up, given its scramble.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelRandomization {
+    /// Add the scramble to each point and wrap around (a Cranley-Patterson rotation). Fine
+    /// for sequences with no strata to keep, but it breaks up elementary intervals.
+    Rotation,
+    /// Owen scramble the points in base 2, seeded by the scramble, which keeps the strata
+    /// of (0,2) sequences.
+    Owen,
+    /// Owen scramble the digits of each dimension's points in its Halton base, seeded by
+    /// the scramble.
+    Halton,
+}
+
+/// The R sequence, from Martin Roberts' "The Unreasonable Effectiveness of Quasirandom
+/// Sequences".
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RSequence;
+
+impl Sampler for RSequence {
+    fn fill(&self, dims: usize, _dim: usize, seed: u64, first_sample: u64, buf: &mut [f32]) {
+        let mut seq = quasi_rd::Sequence::new_with_offset(dims, (seed << 32) + first_sample);
+        seq.fill_with_samples_f32(buf);
+    }
+
+    fn pixel_randomization(&self) -> PixelRandomization {
+        PixelRandomization::Rotation
+    }
+}
+
+/// Uncorrelated random points, as a baseline to compare the others against.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Random;
+
+impl Sampler for Random {
+    fn fill(&self, _dims: usize, _dim: usize, seed: u64, first_sample: u64, buf: &mut [f32]) {
+        let mut rng = SmallRng::seed_from_u64(mix_bits(seed ^ mix_bits(first_sample)));
+        for s in buf.iter_mut() {
+            *s = rng.gen();
+        }
+    }
+
+    fn pixel_randomization(&self) -> PixelRandomization {
+        PixelRandomization::Rotation
+    }
+}
+
+/// The first two dimensions of the Sobol sequence, Owen scrambled with the hash from Burley
+/// 2020, "Practical Hash-based Owen Scrambling". Sets never have more than two dimensions,
+/// and each set is scrambled differently, so the higher dimensions aren't needed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OwenSobol;
+
+impl Sampler for OwenSobol {
+    fn fill(&self, dims: usize, _dim: usize, seed: u64, first_sample: u64, buf: &mut [f32]) {
+        assert!(dims <= 2, "OwenSobol only supports up to 2 dimensions");
+
+        let seeds = [mix_bits(seed) as u32, mix_bits(seed ^ (1 << 48)) as u32];
+
+        for (i, point) in buf.chunks_exact_mut(dims).enumerate() {
+            // indices past 2^32 wrap around to the start of the sequence
+            let index = (first_sample + i as u64) as u32;
+            for (d, value) in point.iter_mut().enumerate() {
+                *value = to_unit_f32(owen_scramble(sobol(index, d), seeds[d]));
+            }
+        }
+    }
+
+    fn pixel_randomization(&self) -> PixelRandomization {
+        PixelRandomization::Owen
+    }
+}
+
+/// The Halton sequence, taking successive prime bases for each dimension of a camera sample
+/// and randomized by shifting each digit by an amount hashed from the digits before it, which
+/// keeps the stratification like Owen scrambling does.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Halton;
+
+impl Sampler for Halton {
+    fn fill(&self, dims: usize, dim: usize, seed: u64, first_sample: u64, buf: &mut [f32]) {
+        let bases = (dim..dim + dims).map(nth_prime).collect::<Vec<_>>();
+        let seeds = (0..dims)
+            .map(|d| mix_bits(seed ^ ((d as u64) << 48)))
+            .collect::<Vec<_>>();
+
+        for (i, point) in buf.chunks_exact_mut(dims).enumerate() {
+            let index = first_sample + i as u64;
+            for ((value, base), seed) in point.iter_mut().zip(bases.iter()).zip(seeds.iter()) {
+                *value = scrambled_radical_inverse(*base, index, *seed);
+            }
+        }
+    }
+
+    fn pixel_randomization(&self) -> PixelRandomization {
+        PixelRandomization::Halton
+    }
+}
+
+/// Progressive multi-jittered (0,2) sequences, from Christensen et al. 2018, "Progressive
+/// Multi-Jittered Sample Sequences". 1D sets use the first dimension of a 2D sequence.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Pmj02;
+
+impl Sampler for Pmj02 {
+    fn fill(&self, dims: usize, _dim: usize, seed: u64, first_sample: u64, buf: &mut [f32]) {
+        assert!(dims <= 2, "Pmj02 only supports up to 2 dimensions");
+
+        // points depend on every point before them, so generate from the start
+        let first_sample = first_sample as usize;
+        let points = pmj02(first_sample + buf.len() / dims, mix_bits(seed));
+
+        for (point, value) in buf
+            .chunks_exact_mut(dims)
+            .zip(points[first_sample..].iter())
+        {
+            point.copy_from_slice(&[value.0, value.1][..dims]);
+        }
+    }
+
+    fn pixel_randomization(&self) -> PixelRandomization {
+        PixelRandomization::Owen
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnySampler {
+    RSequence(RSequence),
+    Random(Random),
+    OwenSobol(OwenSobol),
+    Halton(Halton),
+    Pmj02(Pmj02),
+}
+
+impl AnySampler {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "rd" => AnySampler::RSequence(RSequence),
+            "random" => AnySampler::Random(Random),
+            "sobol" => AnySampler::OwenSobol(OwenSobol),
+            "halton" => AnySampler::Halton(Halton),
+            "pmj02" => AnySampler::Pmj02(Pmj02),
+            _ => return Err(format!("Unknown sampler: {}", name)),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnySampler::RSequence(_) => "rd",
+            AnySampler::Random(_) => "random",
+            AnySampler::OwenSobol(_) => "sobol",
+            AnySampler::Halton(_) => "halton",
+            AnySampler::Pmj02(_) => "pmj02",
+        }
+    }
+}
+
+impl Default for AnySampler {
+    fn default() -> Self {
+        AnySampler::RSequence(RSequence)
+    }
+}
+
+impl Sampler for AnySampler {
+    fn fill(&self, dims: usize, dim: usize, seed: u64, first_sample: u64, buf: &mut [f32]) {
+        match self {
+            AnySampler::RSequence(s) => s.fill(dims, dim, seed, first_sample, buf),
+            AnySampler::Random(s) => s.fill(dims, dim, seed, first_sample, buf),
+            AnySampler::OwenSobol(s) => s.fill(dims, dim, seed, first_sample, buf),
+            AnySampler::Halton(s) => s.fill(dims, dim, seed, first_sample, buf),
+            AnySampler::Pmj02(s) => s.fill(dims, dim, seed, first_sample, buf),
+        }
+    }
+
+    fn pixel_randomization(&self) -> PixelRandomization {
+        match self {
+            AnySampler::RSequence(s) => s.pixel_randomization(),
+            AnySampler::Random(s) => s.pixel_randomization(),
+            AnySampler::OwenSobol(s) => s.pixel_randomization(),
+            AnySampler::Halton(s) => s.pixel_randomization(),
+            AnySampler::Pmj02(s) => s.pixel_randomization(),
+        }
+    }
+}
+
+/// The splitmix64 finalizer, mixing every bit of `x` into every bit of the result.
+fn mix_bits(mut x: u64) -> u64 {
+    x ^= x >> 31;
+    x = x.wrapping_mul(0x7fb5_d329_728e_a185);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x81da_def4_bc2d_d44d);
+    x ^= x >> 33;
+    x
+}
+
+fn to_unit_f32(x: u32) -> f32 {
+    (x >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+fn from_unit_f32(x: f32) -> u32 {
+    (x as f64 * (1u64 << 32) as f64) as u32
+}
+
+/// Point `index` of dimension `dim` of the Sobol sequence, as a 0.32 fixed point number.
+fn sobol(index: u32, dim: usize) -> u32 {
+    match dim {
+        // the van der Corput sequence
+        0 => index.reverse_bits(),
+        // direction numbers from the primitive polynomial x + 1
+        _ => {
+            let mut result = 0;
+            let mut direction = 1u32 << 31;
+            let mut index = index;
+            while index != 0 {
+                if index & 1 != 0 {
+                    result ^= direction;
+                }
+                index >>= 1;
+                direction ^= direction >> 1;
+            }
+            result
+        }
+    }
+}
+
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+fn nth_prime(n: usize) -> u64 {
+    let mut found = 0;
+    let mut candidate = 1u64;
+    loop {
+        candidate += 1;
+        if (2..)
+            .take_while(|d| d * d <= candidate)
+            .all(|d| candidate % d != 0)
+        {
+            if found == n {
+                return candidate;
+            }
+            found += 1;
+        }
+    }
+}
+
+/// The digits of `index` in `base` mirrored about the radix point, with each digit shifted by
+/// an amount hashed from `seed` and the digits less significant than it in `index`.
+fn scrambled_radical_inverse(base: u64, mut index: u64, seed: u64) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0f64;
+    let mut prefix = 0u64;
+    let mut depth = 0u64;
+    let mut result = 0.0f64;
+
+    // keep going once the digits of `index` run out so that the trailing zeros are scrambled
+    // too, until the digits are too small to show up in an f32
+    while inv_base_m > 1e-8 {
+        let digit = index % base;
+        index /= base;
+
+        let shift = mix_bits(seed ^ mix_bits(prefix ^ (depth << 56))) % base;
+        inv_base_m *= inv_base;
+        result += ((digit + shift) % base) as f64 * inv_base_m;
+
+        prefix = prefix * base + digit;
+        depth += 1;
+    }
+
+    (result as f32).min(ONE_MINUS_EPSILON)
+}
+
+/// Owen scramble `value` in `base`, shifting each of its digits by an amount hashed from
+/// `seed` and the digits more significant than it, which keeps the base's strata.
+fn scramble_digits(value: f32, base: u64, seed: u64) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0f64;
+    let mut remainder = value as f64;
+    let mut prefix = 0u64;
+    let mut depth = 0u64;
+    let mut result = 0.0f64;
+
+    while inv_base_m > 1e-8 {
+        remainder *= base as f64;
+        let digit = (remainder as u64).min(base - 1);
+        remainder -= digit as f64;
+
+        let shift = mix_bits(seed ^ mix_bits(prefix ^ (depth << 56))) % base;
+        inv_base_m *= inv_base;
+        result += ((digit + shift) % base) as f64 * inv_base_m;
+
+        prefix = prefix * base + digit;
+        depth += 1;
+    }
+
+    (result as f32).min(ONE_MINUS_EPSILON)
+}
+
+/// The first `count` points of a pmj02 sequence. Every prefix of the sequence with a power of
+/// two length `n` has one point in each elementary interval of area `1/n`, so it is stratified
+/// in 1D and in every grid of rectangles, and points are jittered within those.
+fn pmj02(count: usize, seed: u64) -> Vec<(f32, f32)> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut points: Vec<(f64, f64)> = Vec::with_capacity(count.next_power_of_two());
+    points.push((rng.gen(), rng.gen()));
+
+    while points.len() < count {
+        let n = points.len();
+        let log_n = n.trailing_zeros();
+        // each cell of this grid already holds one or two points in its quadrants
+        let cells = 1usize << (log_n / 2);
+        let quadrant = |point: (f64, f64)| {
+            (
+                (point.0 * (cells * 2) as f64) as usize,
+                (point.1 * (cells * 2) as f64) as usize,
+            )
+        };
+
+        let mut grid = Pmj02Grid::new(&points, n * 2);
+
+        if log_n % 2 == 0 {
+            // one point per cell, so add one in the diagonally opposite quadrant
+            for i in 0..n {
+                let (qx, qy) = quadrant(points[i]);
+                let point = grid.generate(qx ^ 1, qy ^ 1, cells * 2, &mut rng);
+                points.push(point);
+            }
+        } else {
+            // two points in diagonally opposite quadrants of each cell, so fill the other two,
+            // in a random order so neither axis is favored
+            let mut remaining = Vec::with_capacity(n / 2);
+            for i in 0..n / 2 {
+                let (qx, qy) = quadrant(points[i]);
+                let (first, second) = if rng.gen() {
+                    ((qx ^ 1, qy), (qx, qy ^ 1))
+                } else {
+                    ((qx, qy ^ 1), (qx ^ 1, qy))
+                };
+                let point = grid.generate(first.0, first.1, cells * 2, &mut rng);
+                points.push(point);
+                remaining.push(second);
+            }
+            for (qx, qy) in remaining {
+                let point = grid.generate(qx, qy, cells * 2, &mut rng);
+                points.push(point);
+            }
+        }
+    }
+
+    points
+        .iter()
+        .take(count)
+        .map(|&(x, y)| (round_down(x), round_down(y)))
+        .collect()
+}
+
+/// Convert to the f32 at or below `x`, since rounding to the nearest could carry a point
+/// jittered to the very top of its stratum into the next one.
+fn round_down(x: f64) -> f32 {
+    let y = x as f32;
+    let y = if y as f64 > x {
+        f32::from_bits(y.to_bits() - 1)
+    } else {
+        y
+    };
+    y.min(ONE_MINUS_EPSILON)
+}
+
+/// Which elementary intervals of area `1/total` hold a point, for each of their shapes.
+struct Pmj02Grid {
+    log_total: usize,
+    // for shape `k`, the intervals are `2^k` wide in x and `2^(log_total - k)` in y
+    occupied: Vec<Vec<bool>>,
+}
+
+impl Pmj02Grid {
+    fn new(points: &[(f64, f64)], total: usize) -> Self {
+        let log_total = total.trailing_zeros() as usize;
+        let mut grid = Pmj02Grid {
+            log_total,
+            occupied: vec![vec![false; total]; log_total + 1],
+        };
+        for point in points {
+            let (x, y) = grid.strata(*point);
+            grid.mark(x, y);
+        }
+        grid
+    }
+
+    /// The finest 1D strata in x and y that `point` falls in.
+    fn strata(&self, point: (f64, f64)) -> (usize, usize) {
+        let total = 1 << self.log_total;
+        (
+            ((point.0 * total as f64) as usize).min(total - 1),
+            ((point.1 * total as f64) as usize).min(total - 1),
+        )
+    }
+
+    fn interval(&self, k: usize, x: usize, y: usize) -> usize {
+        (x >> (self.log_total - k)) + (y >> k) * (1 << k)
+    }
+
+    fn is_free(&self, x: usize, y: usize) -> bool {
+        (0..=self.log_total).all(|k| !self.occupied[k][self.interval(k, x, y)])
+    }
+
+    fn mark(&mut self, x: usize, y: usize) {
+        for k in 0..=self.log_total {
+            let interval = self.interval(k, x, y);
+            self.occupied[k][interval] = true;
+        }
+    }
+
+    /// A new point in quadrant `(qx, qy)` of a `quadrants` by `quadrants` grid that falls in
+    /// no occupied elementary interval, which is then marked as occupied.
+    fn generate(
+        &mut self,
+        qx: usize,
+        qy: usize,
+        quadrants: usize,
+        rng: &mut SmallRng,
+    ) -> (f64, f64) {
+        let total = 1 << self.log_total;
+        let strata = total / quadrants;
+        let free = |range: std::ops::Range<usize>, occupied: &[bool]| {
+            let free = range.clone().filter(|&i| !occupied[i]).collect::<Vec<_>>();
+            if free.is_empty() {
+                range.collect()
+            } else {
+                free
+            }
+        };
+        let free_x = free(
+            qx * strata..(qx + 1) * strata,
+            &self.occupied[self.log_total],
+        );
+        let free_y = free(qy * strata..(qy + 1) * strata, &self.occupied[0]);
+
+        // guessing is usually quick, but search every combination if it isn't
+        let mut choice = None;
+        for _ in 0..64 {
+            let x = free_x[rng.gen_range(0, free_x.len())];
+            let y = free_y[rng.gen_range(0, free_y.len())];
+            if self.is_free(x, y) {
+                choice = Some((x, y));
+                break;
+            }
+        }
+        if choice.is_none() {
+            let candidates = free_x
+                .iter()
+                .flat_map(|&x| free_y.iter().map(move |&y| (x, y)))
+                .filter(|&(x, y)| self.is_free(x, y))
+                .collect::<Vec<_>>();
+            if !candidates.is_empty() {
+                choice = Some(candidates[rng.gen_range(0, candidates.len())]);
+            }
+        }
+        // should never happen, but only costs a little stratification if it does
+        let (x, y) = choice.unwrap_or((free_x[0], free_y[0]));
+
+        self.mark(x, y);
+        (
+            (x as f64 + rng.gen::<f64>()) / total as f64,
+            (y as f64 + rng.gen::<f64>()) / total as f64,
+        )
+    }
+}
 
 pub struct Samples {
     pub samples: usize,
     pub samples_1d: Vec<f32>,
     pub samples_2d: Vec<f32>,
+    sets_1d: usize,
+    randomization: PixelRandomization,
+    // the Halton base of each dimension, when the points are Halton scrambled per pixel
+    bases: Vec<u64>,
 }
 
 impl Samples {
-    pub fn new_rd(samples: usize, sets_1d: usize, sets_2d: usize, offset: u64) -> Self {
+    /// Tables of `samples` points from `sampler` for each of `sets_1d` 1D sets and `sets_2d`
+    /// 2D sets. `first_sample` skips that many samples into each sequence, so that successive
+    /// passes over the same pixels continue the sequences rather than repeating them.
+    pub fn new<S: Sampler + ?Sized>(
+        sampler: &S,
+        samples: usize,
+        sets_1d: usize,
+        sets_2d: usize,
+        offset: u64,
+        first_sample: u64,
+    ) -> Self {
         let mut samples_1d = vec![0f32; samples * sets_1d];
         let mut samples_2d = vec![0f32; samples * 2 * sets_2d];
 
         for i in 0..sets_1d {
-            let mut seq_1d = quasi_rd::Sequence::new_with_offset(1, (offset + i as u64) << 32);
-            seq_1d.fill_with_samples_f32(&mut samples_1d[samples * i..samples * (i + 1)]);
+            sampler.fill(
+                1,
+                i,
+                offset + i as u64,
+                first_sample,
+                &mut samples_1d[samples * i..samples * (i + 1)],
+            );
         }
 
         for i in 0..sets_2d {
-            let mut seq_2d = quasi_rd::Sequence::new_with_offset(2, (offset + sets_1d as u64 + i as u64) << 32);
-            seq_2d.fill_with_samples_f32(&mut samples_2d[samples * 2 * i..2 * samples * (i + 1)]);
+            sampler.fill(
+                2,
+                sets_1d + i * 2,
+                offset + sets_1d as u64 + i as u64,
+                first_sample,
+                &mut samples_2d[samples * 2 * i..2 * samples * (i + 1)],
+            );
         }
 
+        let randomization = sampler.pixel_randomization();
+        let bases = match randomization {
+            PixelRandomization::Halton => (0..sets_1d + 2 * sets_2d).map(nth_prime).collect(),
+            _ => Vec::new(),
+        };
+
         Self {
             samples,
             samples_1d,
             samples_2d,
+            sets_1d,
+            randomization,
+            bases,
         }
     }
 
-    #[allow(dead_code)]
-    pub fn new_random(samples: usize, sets_1d: usize, sets_2d: usize) -> Self {
-        let mut samples_1d = vec![0f32; samples * sets_1d];
-        let mut samples_2d = vec![0f32; samples * 2 * sets_2d];
-
-        let mut rng = SmallRng::from_rng(thread_rng()).unwrap();
-
-        for s in samples_1d.iter_mut() {
-            *s = rng.gen();
-        }
-
-        for s in samples_2d.iter_mut() {
-            *s = rng.gen();
-        }
-
-        Self {
-            samples,
-            samples_1d,
-            samples_2d,
+    /// Randomize `value`, from dimension `dim` of a camera sample, by a pixel's `scramble`
+    /// so that neighboring pixels don't share sample positions.
+    #[inline]
+    fn randomize(&self, value: f32, dim: usize, scramble: f32) -> f32 {
+        match self.randomization {
+            PixelRandomization::Rotation => (value + scramble).fract(),
+            PixelRandomization::Owen => {
+                let seed = mix_bits(scramble.to_bits() as u64 ^ ((dim as u64) << 32));
+                to_unit_f32(owen_scramble(from_unit_f32(value), seed as u32))
+            }
+            PixelRandomization::Halton => {
+                let seed = mix_bits(scramble.to_bits() as u64 ^ ((dim as u64) << 32));
+                scramble_digits(value, self.bases[dim], seed)
+            }
         }
     }
 
     #[inline]
     pub fn sample_1d(&self, sample: usize, scramble: f32, set: usize) -> f32 {
-        (self.samples_1d[sample + self.samples * set] + scramble).fract()
+        self.randomize(self.samples_1d[sample + self.samples * set], set, scramble)
     }
 
     #[inline]
@@ -90,7 +602,11 @@ impl Samples {
 
     #[inline]
     pub fn sample_2d(&self, dim: usize, sample: usize, scramble: f32, set: usize) -> f32 {
-        (self.samples_2d[dim + sample * 2 + self.samples * 2 * set] + scramble).fract()
+        self.randomize(
+            self.samples_2d[dim + sample * 2 + self.samples * 2 * set],
+            self.sets_1d + set * 2 + dim,
+            scramble,
+        )
     }
 
     #[inline]
@@ -125,3 +641,74 @@ impl Samples {
         ])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that every power of two prefix of `points` has one point in each elementary
+    /// interval of its size.
+    fn assert_stratified(points: &[(f32, f32)]) {
+        let mut n = 1;
+        while n <= points.len() {
+            let log_n = n.trailing_zeros();
+            for k in 0..=log_n {
+                let (cols, rows) = (1usize << k, 1usize << (log_n - k));
+                let mut occupied = vec![false; n];
+                for (i, &(x, y)) in points[..n].iter().enumerate() {
+                    let cell = (x * cols as f32) as usize + (y * rows as f32) as usize * cols;
+                    assert!(
+                        !occupied[cell],
+                        "point {} of the first {} shares a {}x{} interval",
+                        i, n, cols, rows
+                    );
+                    occupied[cell] = true;
+                }
+            }
+            n *= 2;
+        }
+    }
+
+    fn sobol_points(count: u32, seeds: Option<[u32; 2]>) -> Vec<(f32, f32)> {
+        (0..count)
+            .map(|i| match seeds {
+                Some(seeds) => (
+                    to_unit_f32(owen_scramble(sobol(i, 0), seeds[0])),
+                    to_unit_f32(owen_scramble(sobol(i, 1), seeds[1])),
+                ),
+                None => (to_unit_f32(sobol(i, 0)), to_unit_f32(sobol(i, 1))),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pmj02_is_stratified() {
+        for seed in 0..8 {
+            let points = pmj02(256, mix_bits(seed));
+            assert_eq!(points.len(), 256);
+            assert_stratified(&points);
+        }
+    }
+
+    #[test]
+    fn pmj02_partial_prefix_matches_full_sequence() {
+        let full = pmj02(64, 7);
+        let partial = pmj02(40, 7);
+        assert_eq!(&full[..40], &partial[..]);
+    }
+
+    #[test]
+    fn sobol_is_stratified() {
+        assert_stratified(&sobol_points(1024, None));
+    }
+
+    #[test]
+    fn owen_scrambled_sobol_is_stratified() {
+        for seed in 0..8 {
+            let seeds = [mix_bits(seed) as u32, mix_bits(seed ^ (1 << 48)) as u32];
+            let points = sobol_points(1024, Some(seeds));
+            assert_ne!(points, sobol_points(1024, None));
+            assert_stratified(&points);
+        }
+    }
+}
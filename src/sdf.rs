@@ -1,3 +1,4 @@
+use crate::animation::WSequenced;
 use crate::hitable::{Hitable, WHit, WShadingPoint};
 use crate::material::MaterialHandle;
 use crate::math::{f32x4, Wec3};
@@ -8,6 +9,24 @@ use sdfu::*;
 const MAX_MARCHES: u32 = 256;
 const MAX_VIS_MARCHES: u32 = 100;
 
+/// An SDF which may change over time. The SDF returned by `sdf_at` evaluates each lane
+/// with the parameters of the SDF at that lane's time.
+pub trait SequencedSDF: Send + Sync {
+    type SDF: SDF<f32x4, Wec3>;
+
+    fn sdf_at(&self, time: f32x4) -> Self::SDF;
+}
+
+/// Any plain SDF is a sequenced SDF which is the same at every time.
+impl<S: SDF<f32x4, Wec3> + Clone + Send + Sync> SequencedSDF for S {
+    type SDF = S;
+
+    #[inline]
+    fn sdf_at(&self, _time: f32x4) -> S {
+        self.clone()
+    }
+}
+
 pub struct TracedSDF<S> {
     sdf: S,
     material: MaterialHandle,
@@ -19,14 +38,16 @@ impl<S> TracedSDF<S> {
     }
 }
 
-impl<S: SDF<f32x4, Wec3> + Send + Sync> Hitable for TracedSDF<S> {
+impl<S: SequencedSDF> Hitable for TracedSDF<S> {
     // return 1.0 for not occluded, 0.0 for occluded
-    fn occluded(&self, start: Wec3, end: Wec3, _time: f32x4) -> f32x4 {
+    fn occluded(&self, start: Wec3, end: Wec3, time: f32x4) -> f32x4 {
+        let sdf = self.sdf.sdf_at(time);
+
         let dir = end - start;
         let max_dist = dir.mag();
         let dir = dir / max_dist;
 
-        let dist = self.sdf.dist(start).abs();
+        let dist = sdf.dist(start).abs();
 
         let nan_mask = dist.cmp_nan(dist);
         let gt_mask = dist.cmp_gt(max_dist);
@@ -42,7 +63,7 @@ impl<S: SDF<f32x4, Wec3> + Send + Sync> Hitable for TracedSDF<S> {
             }
 
             let point = dir.mul_add(Wec3::broadcast(t), start);
-            let dist = self.sdf.dist(point).abs();
+            let dist = sdf.dist(point).abs();
 
             hit_mask = dist.cmp_lt(f32x4::from(0.0001 * crate::SDF_DETAIL_SCALE).max(f32x4::from(0.00001 * crate::SDF_DETAIL_SCALE) * t));
 
@@ -56,14 +77,16 @@ impl<S: SDF<f32x4, Wec3> + Send + Sync> Hitable for TracedSDF<S> {
     }
 
     fn hit(&self, ray: &WRay, t_max: f32x4, hit_threshold_at: &dyn Fn(f32x4) -> f32x4) -> f32x4 {
-        let dist = self.sdf.dist(ray.origin).abs();
+        let sdf = self.sdf.sdf_at(ray.time);
+
+        let dist = sdf.dist(ray.origin).abs();
         let mut t = dist;
 
         let nan_mask = t.cmp_nan(t);
 
         for _march in 0..MAX_MARCHES {
             let point = ray.point_at(t);
-            let dist = self.sdf.dist(point).abs();
+            let dist = sdf.dist(point).abs();
 
             let hit_mask = dist.cmp_lt(
                 f32x4::from(0.00005 * crate::SDF_DETAIL_SCALE)
@@ -90,7 +113,8 @@ impl<S: SDF<f32x4, Wec3> + Send + Sync> Hitable for TracedSDF<S> {
 
         let half_pixel_size = f32x4::from(0.0001).max(f32x4::from(crate::SDF_DETAIL_SCALE) * half_pixel_size_at(hit.t));
 
-        let normals = self.sdf.normals_fast(half_pixel_size);
+        let sdf = self.sdf.sdf_at(hit.ray.time);
+        let normals = sdf.normals_fast(half_pixel_size);
 
         let normal = normals.normal_at(point);
         (
@@ -110,17 +134,77 @@ pub struct MandelBox {
 }
 
 impl MandelBox {
+    #[allow(dead_code)]
     pub fn new(iterations: usize, box_fold: BoxFold, sphere_fold: SphereFold, scale: f32) -> Self {
+        Self::new_wide(iterations, box_fold, sphere_fold, scale.into())
+    }
+
+    /// Create a `MandelBox` which may have different parameters in each lane.
+    pub fn new_wide(
+        iterations: usize,
+        box_fold: BoxFold,
+        sphere_fold: SphereFold,
+        scale: f32x4,
+    ) -> Self {
         Self {
             iterations,
             box_fold,
             sphere_fold,
-            scale: scale.into(),
-            scale_vec: Wec3::broadcast(scale.into()),
+            scale,
+            scale_vec: Wec3::broadcast(scale),
+        }
+    }
+}
+
+/// A `MandelBox` whose scale and fold radii are sequenced over time.
+pub struct AnimatedMandelBox<SC, BF, MR, FR> {
+    iterations: usize,
+    scale: SC,
+    box_fold: BF,
+    min_radius: MR,
+    fixed_radius: FR,
+}
+
+impl<SC, BF, MR, FR> AnimatedMandelBox<SC, BF, MR, FR> {
+    pub fn new(
+        iterations: usize,
+        box_fold: BF,
+        min_radius: MR,
+        fixed_radius: FR,
+        scale: SC,
+    ) -> Self {
+        Self {
+            iterations,
+            scale,
+            box_fold,
+            min_radius,
+            fixed_radius,
         }
     }
 }
 
+impl<SC, BF, MR, FR> SequencedSDF for AnimatedMandelBox<SC, BF, MR, FR>
+where
+    SC: WSequenced<f32x4>,
+    BF: WSequenced<f32x4>,
+    MR: WSequenced<f32x4>,
+    FR: WSequenced<f32x4>,
+{
+    type SDF = MandelBox;
+
+    fn sdf_at(&self, time: f32x4) -> MandelBox {
+        MandelBox::new_wide(
+            self.iterations,
+            BoxFold::new_wide(self.box_fold.sample_at(time)),
+            SphereFold::new_wide(
+                self.min_radius.sample_at(time),
+                self.fixed_radius.sample_at(time),
+            ),
+            self.scale.sample_at(time),
+        )
+    }
+}
+
 impl SDF<f32x4, Wec3> for MandelBox {
     fn dist(&self, mut p: Wec3) -> f32x4 {
         let offset = p;
@@ -147,8 +231,13 @@ pub struct BoxFold {
 }
 
 impl BoxFold {
+    #[allow(dead_code)]
     pub fn new(side_length: f32) -> Self {
-        let l = Wec3::broadcast(side_length.into());
+        Self::new_wide(side_length.into())
+    }
+
+    pub fn new_wide(side_length: f32x4) -> Self {
+        let l = Wec3::broadcast(side_length);
         BoxFold {
             l,
             neg_l: -l,
@@ -168,9 +257,14 @@ pub struct SphereFold {
 }
 
 impl SphereFold {
+    #[allow(dead_code)]
     pub fn new(min_radius: f32, fixed_radius: f32) -> Self {
-        let min_rad_sq = (min_radius * min_radius).into();
-        let fixed_rad_sq = (fixed_radius * fixed_radius).into();
+        Self::new_wide(min_radius.into(), fixed_radius.into())
+    }
+
+    pub fn new_wide(min_radius: f32x4, fixed_radius: f32x4) -> Self {
+        let min_rad_sq = min_radius * min_radius;
+        let fixed_rad_sq = fixed_radius * fixed_radius;
         Self {
             min_rad_sq,
             fixed_rad_sq,
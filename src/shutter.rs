@@ -0,0 +1,112 @@
+use crate::math::{f32x4, map_lanes};
+
+use std::ops::Range;
+
+/// How open the shutter is over the course of a single exposure, which weights the times
+/// that samples are taken at.
+#[derive(Clone, Copy, Debug)]
+pub enum ShutterCurve {
+    /// Fully open for the whole exposure.
+    Box,
+    /// Opens linearly until halfway through the exposure, then closes linearly.
+    Triangle,
+    /// Spends the first `open` and the last `close` fractions of the exposure opening
+    /// and closing linearly, and is fully open in between.
+    Trapezoid { open: f32, close: f32 },
+}
+
+impl ShutterCurve {
+    /// Map a uniform sample in `[0, 1)` to a fraction of the way through the exposure,
+    /// distributed proportionally to how open the shutter is.
+    pub fn sample(&self, u: f32) -> f32 {
+        let (open, close) = match *self {
+            ShutterCurve::Box => return u,
+            ShutterCurve::Triangle => (0.5, 0.5),
+            ShutterCurve::Trapezoid { open, close } => {
+                let total = (open + close).max(1.0);
+                (open.max(0.0) / total, close.max(0.0) / total)
+            }
+        };
+
+        // height of the fully open section, such that the curve integrates to one
+        let height = 1.0 / (1.0 - (open + close) / 2.0);
+
+        let opening_area = height * open / 2.0;
+        let open_area = height * (1.0 - open - close);
+
+        if u < opening_area {
+            (2.0 * u * open / height).sqrt()
+        } else if u < opening_area + open_area {
+            open + (u - opening_area) / height
+        } else {
+            let remaining = (1.0 - u).max(0.0);
+            1.0 - (2.0 * remaining * close / height).sqrt()
+        }
+    }
+}
+
+/// A rolling shutter exposes each scanline in turn from the top of the image to the bottom,
+/// so the last scanline starts being exposed `readout_time` seconds after the first.
+#[derive(Clone, Copy, Debug)]
+pub struct RollingShutter {
+    pub readout_time: f32,
+}
+
+/// When and how the film is exposed for a single frame.
+#[derive(Clone, Copy, Debug)]
+pub struct Shutter {
+    pub open: f32,
+    pub exposure: f32,
+    pub curve: ShutterCurve,
+    pub rolling: Option<RollingShutter>,
+}
+
+impl Shutter {
+    /// A global box shutter which opens at `open` for `exposure` seconds.
+    pub fn new(open: f32, exposure: f32) -> Self {
+        Shutter {
+            open,
+            exposure,
+            curve: ShutterCurve::Box,
+            rolling: None,
+        }
+    }
+
+    pub fn with_curve(mut self, curve: ShutterCurve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    pub fn with_rolling_shutter(mut self, readout_time: f32) -> Self {
+        self.rolling = Some(RollingShutter { readout_time });
+        self
+    }
+
+    /// The full range of time any part of the film is exposed for.
+    pub fn time_range(&self) -> Range<f32> {
+        let readout_time = self.rolling.map(|r| r.readout_time).unwrap_or(0.0);
+        self.open..self.open + self.exposure + readout_time
+    }
+
+    /// The times the shutter opens and closes for the scanline `scanline` of the way down
+    /// from the top of the image.
+    pub fn scanline_times(&self, scanline: f32x4) -> (f32x4, f32x4) {
+        let open = match self.rolling {
+            Some(rolling) => f32x4::from(self.open) + f32x4::from(rolling.readout_time) * scanline,
+            None => f32x4::from(self.open),
+        };
+        (open, open + f32x4::from(self.exposure))
+    }
+
+    /// Sample times for a pixel on the scanline `scanline` of the way down from the top
+    /// of the image, from the uniform samples `u`.
+    pub fn sample_time(&self, u: f32x4, scanline: f32) -> f32x4 {
+        let open = match self.rolling {
+            Some(rolling) => self.open + rolling.readout_time * scanline,
+            None => self.open,
+        };
+        let exposure = self.exposure;
+        let curve = self.curve;
+        map_lanes(u, |u| open + exposure * curve.sample(u))
+    }
+}
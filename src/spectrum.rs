@@ -51,6 +51,14 @@ macro_rules! srgbs {
             pub fn max_channel(&self) -> $tt {
                 self.0.component_max()
             }
+
+            /// Relative luminance, using the Rec. 709 weights.
+            #[allow(dead_code)]
+            pub fn luminance(&self) -> $tt {
+                self.0.x * $tt::from(0.2126)
+                    + self.0.y * $tt::from(0.7152)
+                    + self.0.z * $tt::from(0.0722)
+            }
         }
 
         impl Sum for $n {
@@ -49,6 +49,11 @@ impl<TR: WSequenced<Wec3>> Hitable for Sphere<TR> {
         }
     }
 
+    fn move_point(&self, point: Wec3, from: f32x4, to: f32x4) -> Wec3 {
+        point - WSequenced::sample_at(&self.transform_seq, from)
+            + WSequenced::sample_at(&self.transform_seq, to)
+    }
+
     fn hit(&self, ray: &WRay, t_max: f32x4, _hit_threshold_at: &dyn Fn(f32x4) -> f32x4) -> f32x4 {
         let origin = WSequenced::sample_at(&self.transform_seq, ray.time);
         let oc = ray.origin - origin;
@@ -1,11 +1,27 @@
 use crate::camera::CameraStore;
 use crate::hitable::HitableStore;
-use crate::light::Light;
+use crate::light::LightStore;
 use crate::material::MaterialStore;
 
+use std::ops::Range;
+
 pub struct World {
     pub hitables: HitableStore,
-    pub lights: Vec<Box<dyn Light>>,
+    pub lights: LightStore,
     pub materials: MaterialStore,
     pub cameras: CameraStore,
+    /// Radius of a sphere around the origin which bounds everything in the world.
+    pub radius: f32,
+}
+
+impl World {
+    /// Let every camera update any per-frame state, such as autofocus, before rendering the
+    /// frame whose shutter is open over `time_range`.
+    pub fn prepare_frame(&mut self, time_range: Range<f32>) {
+        let hitables = &self.hitables;
+        let clip = 0.0..self.radius * 2.0;
+        for camera in self.cameras.iter_mut() {
+            camera.prepare_frame(hitables, clip.clone(), time_range.clone());
+        }
+    }
 }